@@ -17,6 +17,7 @@ use serde::{Deserialize, Serialize};
 use url::Url;
 
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct DbPost {
     pub text: String,
     pub ap_id: ObjectId<DbPost>,
@@ -29,6 +29,8 @@ pub async fn new_instance(
         .signed_fetch_actor(&system_user)
         .app_data(database)
         .debug(true)
+        // both instances run on localhost
+        .ssrf_allowlist(vec!["127.0.0.0/8".parse()?, "::1/128".parse()?])
         .build()
         .await?;
     Ok(config)
@@ -45,6 +47,7 @@ pub struct Database {
 
 /// Use this to store your federation blocklist, or a database connection needed to retrieve it.
 #[derive(Clone)]
+#[allow(dead_code)]
 struct MyUrlVerifier();
 
 #[async_trait]
@@ -3,14 +3,19 @@
 #![doc = include_str!("../docs/09_sending_activities.md")]
 
 use crate::{
-    config::Data,
+    config::{Data, ReceivedActivity},
     error::Error,
-    http_signatures::sign_request,
+    fetch::object_id::ObjectId,
+    http_signatures::{send_signed_request, PemSigner, Signer},
+    protocol::{
+        integrity_proof::{add_proof, verify_proof},
+        ld_signature::verify_ld_signature,
+    },
     reqwest_shim::ResponseExt,
-    traits::{ActivityHandler, Actor},
+    traits::{ActivityHandler, Actor, Object},
     FEDERATION_CONTENT_TYPE,
 };
-use anyhow::{anyhow, Context};
+use anyhow::anyhow;
 
 use bytes::Bytes;
 use futures::StreamExt;
@@ -18,13 +23,13 @@ use http::{header::HeaderName, HeaderMap, HeaderValue};
 use httpdate::fmt_http_date;
 use itertools::Itertools;
 use openssl::pkey::{PKey, Private};
-use reqwest::Request;
-use reqwest_middleware::ClientWithMiddleware;
+use reqwest::Response;
 use serde::Serialize;
 use std::{
     self,
     fmt::{Debug, Display},
-    time::{Duration, SystemTime},
+    sync::Arc,
+    time::SystemTime,
 };
 use tracing::debug;
 use url::Url;
@@ -32,11 +37,11 @@ use url::Url;
 #[derive(Clone, Debug)]
 /// all info needed to send one activity to one inbox
 pub struct SendActivityTask<'a> {
-    actor_id: &'a Url,
+    actor_id: Url,
     activity_id: &'a Url,
     activity: Bytes,
     inbox: Url,
-    private_key: PKey<Private>,
+    signer: Arc<dyn Signer>,
     http_signature_compat: bool,
 }
 impl Display for SendActivityTask<'_> {
@@ -50,8 +55,8 @@ impl SendActivityTask<'_> {
     ///
     /// - `activity`: The activity to be sent, gets converted to json
     /// - `inboxes`: List of remote actor inboxes that should receive the activity. Ignores local actor
-    ///              inboxes. Should be built by calling [crate::traits::Actor::shared_inbox_or_inbox]
-    ///              for each target actor.
+    ///   inboxes. Should be built by calling [crate::traits::Actor::shared_inbox_or_inbox]
+    ///   for each target actor.
     pub async fn prepare<'a, Activity, Datatype, ActorType>(
         activity: &'a Activity,
         actor: &ActorType,
@@ -64,13 +69,101 @@ impl SendActivityTask<'_> {
         Datatype: Clone,
         ActorType: Actor,
     {
-        let config = &data.config;
         let actor_id = activity.actor();
         let activity_id = activity.id();
-        let activity_serialized: Bytes = serde_json::to_vec(&activity)?.into();
-        let private_key = get_pkey_cached(data, actor).await?;
+        let activity_serialized: Bytes = match actor.ed25519_private_key_pem() {
+            Some(private_key) => {
+                let mut json = serde_json::to_value(activity)?;
+                add_proof(&mut json, actor_id, &private_key).map_err(anyhow::Error::from)?;
+                serde_json::to_vec(&json)?.into()
+            }
+            None => serde_json::to_vec(&activity)?.into(),
+        };
+        let signer = match actor.signer() {
+            Some(signer) => signer,
+            None => Arc::new(PemSigner::from(get_pkey_cached(data, actor).await?)),
+        };
+
+        Ok(Self::build_tasks(
+            actor_id,
+            activity_id,
+            activity_serialized,
+            signer,
+            inboxes,
+            data,
+        )
+        .await)
+    }
 
-        Ok(futures::stream::iter(
+    /// prepare forwarding of an activity which was received in the inbox
+    ///
+    /// The activity is sent exactly as it was received, with an HTTP signature from `forwarder`.
+    /// It needs to contain a valid [Linked Data Signature](crate::protocol::ld_signature) or
+    /// [integrity proof](crate::protocol::integrity_proof) of the original actor, so that
+    /// receivers can verify it. This is checked before forwarding, the original actor is
+    /// dereferenced for this.
+    ///
+    /// - `received`: The activity which should be forwarded, from
+    ///   [Data::received_activity](crate::config::Data::received_activity)
+    /// - `forwarder`: The local actor which signs the forwarded requests
+    /// - `inboxes`: List of remote actor inboxes that should receive the activity. Ignores local
+    ///   actor inboxes.
+    ///
+    /// <https://www.w3.org/TR/activitypub/#inbox-forwarding>
+    pub async fn prepare_forward<'a, ActorType>(
+        received: &'a ReceivedActivity,
+        forwarder: &ActorType,
+        inboxes: Vec<Url>,
+        data: &Data<<ActorType as Object>::DataType>,
+    ) -> Result<Vec<SendActivityTask<'a>>, <ActorType as Object>::Error>
+    where
+        ActorType: Object + Actor,
+        <ActorType as Object>::Error: From<Error> + From<anyhow::Error>,
+        for<'de2> <ActorType as Object>::Kind: serde::Deserialize<'de2>,
+    {
+        let json: serde_json::Value =
+            serde_json::from_slice(&received.body).map_err(Error::other)?;
+        if json.get("signature").is_none() && json.get("proof").is_none() {
+            return Err(Error::Other(anyhow!(
+                "Activity {} has no signature or proof, it can't be forwarded",
+                received.id
+            ))
+            .into());
+        }
+        let actor = ObjectId::<ActorType>::from(received.actor.clone())
+            .dereference(data)
+            .await?;
+        let proof_valid = json.get("proof").is_some() && verify_proof(&json, &actor).is_ok();
+        if !proof_valid {
+            verify_ld_signature(&json, &actor, data).await?;
+        }
+
+        let signer = match forwarder.signer() {
+            Some(signer) => signer,
+            None => Arc::new(PemSigner::from(get_pkey_cached(data, forwarder).await?)),
+        };
+
+        Ok(Self::build_tasks(
+            &forwarder.id(),
+            &received.id,
+            received.body.clone(),
+            signer,
+            inboxes,
+            data,
+        )
+        .await)
+    }
+
+    async fn build_tasks<'a, Datatype: Clone>(
+        actor_id: &Url,
+        activity_id: &'a Url,
+        activity: Bytes,
+        signer: Arc<dyn Signer>,
+        inboxes: Vec<Url>,
+        data: &Data<Datatype>,
+    ) -> Vec<SendActivityTask<'a>> {
+        let config = &data.config;
+        futures::stream::iter(
             inboxes
                 .into_iter()
                 .unique()
@@ -82,16 +175,16 @@ impl SendActivityTask<'_> {
                 return None;
             };
             Some(SendActivityTask {
-                actor_id,
+                actor_id: actor_id.clone(),
                 activity_id,
                 inbox,
-                activity: activity_serialized.clone(),
-                private_key: private_key.clone(),
+                activity: activity.clone(),
+                signer: signer.clone(),
                 http_signature_compat: config.http_signature_compat,
             })
         })
         .collect()
-        .await)
+        .await
     }
 
     /// convert a sendactivitydata to a request, signing and sending it
@@ -99,58 +192,57 @@ impl SendActivityTask<'_> {
         &self,
         data: &Data<Datatype>,
     ) -> Result<(), anyhow::Error> {
-        let req = self
-            .sign(&data.config.client, data.config.request_timeout)
-            .await?;
-        self.send(&data.config.client, req).await
-    }
-    async fn sign(
-        &self,
-        client: &ClientWithMiddleware,
-        timeout: Duration,
-    ) -> Result<Request, anyhow::Error> {
-        let task = self;
-        let request_builder = client
-            .post(task.inbox.to_string())
-            .timeout(timeout)
-            .headers(generate_request_headers(&task.inbox));
-        let request = sign_request(
+        let config = &data.config;
+        let request_builder = || {
+            config
+                .client
+                .post(self.inbox.to_string())
+                .timeout(config.request_timeout)
+                .headers(generate_request_headers(&self.inbox))
+        };
+        let response = send_signed_request(
             request_builder,
-            task.actor_id,
-            task.activity.clone(),
-            task.private_key.clone(),
-            task.http_signature_compat,
+            &self.inbox,
+            &self.actor_id,
+            self.activity.clone(),
+            &*self.signer,
+            self.http_signature_compat,
+            data,
         )
-        .await
-        .context("signing request")?;
-        Ok(request)
+        .await;
+        self.handle_response(response, data.response_size_limits.error_body)
+            .await
     }
 
-    async fn send(
+    async fn handle_response(
         &self,
-        client: &ClientWithMiddleware,
-        request: Request,
+        response: Result<Response, anyhow::Error>,
+        error_body_limit: usize,
     ) -> Result<(), anyhow::Error> {
-        let response = client.execute(request).await;
-
         match response {
             Ok(o) if o.status().is_success() => {
                 debug!("Activity {self} delivered successfully");
                 Ok(())
             }
             Ok(o) if o.status().is_client_error() => {
-                let text = o.text_limited().await.map_err(Error::other)?;
+                let text = o
+                    .text_limited(error_body_limit)
+                    .await
+                    .map_err(Error::other)?;
                 debug!("Activity {self} was rejected, aborting: {text}");
                 Ok(())
             }
             Ok(o) => {
                 let status = o.status();
-                let text = o.text_limited().await.map_err(Error::other)?;
+                let text = o
+                    .text_limited(error_body_limit)
+                    .await
+                    .map_err(Error::other)?;
                 Err(anyhow!(
                     "Activity {self} failure with status {status}: {text}",
                 ))
             }
-            Err(e) => Err(anyhow!("Activity {self} connection failure: {e}")),
+            Err(e) => Err(anyhow!("Activity {self}: {e}")),
         }
     }
 }
@@ -212,12 +304,28 @@ mod tests {
     use bytes::Bytes;
     use http::StatusCode;
     use std::{
-        sync::{atomic::AtomicUsize, Arc},
+        sync::atomic::{AtomicUsize, Ordering},
         time::Instant,
     };
     use tracing::info;
 
-    use crate::{config::FederationConfig, http_signatures::generate_actor_keypair};
+    use crate::{
+        config::FederationConfig,
+        fetch::tests::{local_networks, spawn_test_server},
+        http_signatures::{generate_actor_keypair, HttpSignatureScheme, SignatureStrategy},
+        protocol::ld_signature::sign_ld,
+        traits::tests::{
+            DbConnection,
+            DbUser,
+            ForwardableFollow,
+            DB_RELAY,
+            DB_RELAY_KEYPAIR,
+            DB_USER,
+            DB_USER_ED25519_KEYPAIR,
+            DB_USER_KEYPAIR,
+        },
+    };
+    use serde_json::json;
 
     use super::*;
 
@@ -274,16 +382,17 @@ mod tests {
         let keypair = generate_actor_keypair().unwrap();
 
         let message = SendActivityTask {
-            actor_id: &"http://localhost:8001".parse().unwrap(),
+            actor_id: "http://localhost:8001".parse().unwrap(),
             activity_id: &"http://localhost:8001/activity".parse().unwrap(),
             activity: "{}".into(),
             inbox: "http://localhost:8001".parse().unwrap(),
-            private_key: keypair.private_key().unwrap(),
+            signer: Arc::new(PemSigner::new(&keypair.private_key).unwrap()),
             http_signature_compat: true,
         };
         let data = FederationConfig::builder()
             .app_data(())
             .domain("localhost")
+            .ssrf_allowlist(local_networks())
             .build()
             .await?
             .to_request_data();
@@ -297,4 +406,275 @@ mod tests {
         info!("Queue Sent: {:?}", start.elapsed());
         Ok(())
     }
+
+    /// Rejects all requests which are signed with RFC 9421, like servers without support for it
+    async fn cavage_only_handler(
+        State(state): State<Arc<AtomicUsize>>,
+        headers: HeaderMap,
+    ) -> Result<(), StatusCode> {
+        state.fetch_add(1, Ordering::Relaxed);
+        if headers.contains_key("signature-input") {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_signature_double_knock() -> anyhow::Result<()> {
+        use axum::{routing::post, Router};
+
+        let state = Arc::new(AtomicUsize::new(0));
+        let state_ = state.clone();
+        let base = spawn_test_server(move |_| {
+            Router::new()
+                .route("/", post(cavage_only_handler))
+                .with_state(state_)
+        });
+
+        let keypair = generate_actor_keypair().unwrap();
+        let message = SendActivityTask {
+            actor_id: base.clone(),
+            activity_id: &base.join("activity")?,
+            activity: "{}".into(),
+            inbox: base.clone(),
+            signer: Arc::new(PemSigner::new(&keypair.private_key).unwrap()),
+            http_signature_compat: true,
+        };
+        let data = FederationConfig::builder()
+            .app_data(())
+            .domain("localhost")
+            .ssrf_allowlist(local_networks())
+            .signature_strategy(SignatureStrategy::DoubleKnock)
+            .build()
+            .await?
+            .to_request_data();
+
+        // first attempt with RFC 9421 is rejected, then sent again with draft-cavage
+        message.sign_and_send(&data).await?;
+        assert_eq!(state.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            data.config
+                .signature_scheme_cache
+                .get(&format!("localhost:{}", base.port().unwrap())),
+            Some(HttpSignatureScheme::Cavage)
+        );
+
+        // the accepted scheme is remembered, so only a single request is needed
+        message.sign_and_send(&data).await?;
+        assert_eq!(state.load(Ordering::Relaxed), 3);
+        Ok(())
+    }
+
+    /// Only accepts draft-cavage signatures in compatibility mode, which don't contain the
+    /// `(created)` field
+    async fn compat_only_handler(
+        State(state): State<Arc<AtomicUsize>>,
+        headers: HeaderMap,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        state.fetch_add(1, Ordering::Relaxed);
+        let signature = headers
+            .get("signature")
+            .and_then(|s| s.to_str().ok())
+            .unwrap_or_default();
+        if signature.contains("(created)") {
+            return Err((StatusCode::UNAUTHORIZED, "Invalid signature"));
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_signature_compat_per_host() -> anyhow::Result<()> {
+        use axum::{routing::post, Router};
+
+        let state = Arc::new(AtomicUsize::new(0));
+        let state_ = state.clone();
+        let base = spawn_test_server(move |_| {
+            Router::new()
+                .route("/", post(compat_only_handler))
+                .with_state(state_)
+        });
+
+        let keypair = generate_actor_keypair().unwrap();
+        let message = SendActivityTask {
+            actor_id: base.clone(),
+            activity_id: &base.join("activity")?,
+            activity: "{}".into(),
+            inbox: base.clone(),
+            signer: Arc::new(PemSigner::new(&keypair.private_key).unwrap()),
+            http_signature_compat: false,
+        };
+        let data = FederationConfig::builder()
+            .app_data(())
+            .domain("localhost")
+            .ssrf_allowlist(local_networks())
+            .build()
+            .await?
+            .to_request_data();
+
+        // standard signature is rejected, then sent again in compatibility mode
+        message.sign_and_send(&data).await?;
+        assert_eq!(state.load(Ordering::Relaxed), 2);
+        assert!(
+            data.config
+                .signature_compat_store
+                .requires_compat(&format!("localhost:{}", base.port().unwrap()))
+                .await
+        );
+
+        // compatibility mode is used directly for the same host
+        message.sign_and_send(&data).await?;
+        assert_eq!(state.load(Ordering::Relaxed), 3);
+        Ok(())
+    }
+
+    fn signed_follow() -> serde_json::Value {
+        let mut activity = json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": "https://localhost/123/follow/1",
+            "type": "Follow",
+            "actor": "https://localhost/123",
+            "object": "https://localhost/124",
+        });
+        sign_ld(
+            &mut activity,
+            &DB_USER.federation_id,
+            &DB_USER_KEYPAIR.private_key,
+        );
+        activity
+    }
+
+    fn received(activity: &serde_json::Value) -> ReceivedActivity {
+        ReceivedActivity {
+            id: "https://localhost/123/follow/1".parse().unwrap(),
+            actor: DB_USER.federation_id.clone(),
+            headers: HeaderMap::new(),
+            body: serde_json::to_vec(activity).unwrap().into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prepare_forward() -> anyhow::Result<()> {
+        let data = FederationConfig::builder()
+            .app_data(DbConnection)
+            .domain("example.com")
+            .build()
+            .await?
+            .to_request_data();
+        let forwarder = &*DB_RELAY;
+        let inboxes = vec![
+            "https://remote.com/inbox".parse()?,
+            "https://example.com/inbox".parse()?,
+        ];
+
+        let received_activity = received(&signed_follow());
+        let tasks = SendActivityTask::prepare_forward(
+            &received_activity,
+            forwarder,
+            inboxes.clone(),
+            &data,
+        )
+        .await?;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].actor_id, forwarder.federation_id);
+        assert_eq!(tasks[0].activity, received_activity.body);
+
+        // activities without valid signature from the original actor cant be forwarded
+        let mut tampered = signed_follow();
+        tampered["object"] = "https://localhost/125".into();
+        let mut empty_proof = signed_follow();
+        empty_proof.as_object_mut().unwrap().remove("signature");
+        empty_proof["proof"] = json!({});
+        let mut unsigned = signed_follow();
+        unsigned.as_object_mut().unwrap().remove("signature");
+        for activity in [tampered, empty_proof, unsigned] {
+            let received_activity = received(&activity);
+            let res = SendActivityTask::prepare_forward(
+                &received_activity,
+                forwarder,
+                inboxes.clone(),
+                &data,
+            )
+            .await;
+            assert!(res.is_err(), "{activity}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_forward_activity() -> anyhow::Result<()> {
+        use crate::axum::inbox::{receive_activity, ActivityData};
+        use axum::{routing::post, Router};
+
+        let receiver = FederationConfig::builder()
+            .app_data(DbConnection)
+            .domain("example.com")
+            .debug(true)
+            .build()
+            .await?;
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let accepted_ = accepted.clone();
+        let base = spawn_test_server(move |_| {
+            Router::new().route(
+                "/inbox",
+                post(move |activity_data: ActivityData| async move {
+                    let data = receiver.to_request_data();
+                    receive_activity::<ForwardableFollow, DbUser, DbConnection>(
+                        activity_data,
+                        &data,
+                    )
+                    .await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+                    accepted_.fetch_add(1, Ordering::Relaxed);
+                    Ok::<_, (StatusCode, String)>(())
+                }),
+            )
+        });
+        let data = FederationConfig::builder()
+            .app_data(DbConnection)
+            .domain("relay.localhost")
+            .debug(true)
+            .ssrf_allowlist(local_networks())
+            .build()
+            .await?
+            .to_request_data();
+        let inbox = base.join("inbox")?;
+
+        // the relay signs the request, while the activity is signed by the original actor. The
+        // receiver keeps the relay's signature headers, so it could forward the activity again
+        let mut with_proof = signed_follow();
+        with_proof.as_object_mut().unwrap().remove("signature");
+        add_proof(
+            &mut with_proof,
+            &DB_USER.federation_id,
+            &DB_USER_ED25519_KEYPAIR.private_key,
+        )?;
+        for activity in [signed_follow(), with_proof] {
+            let received_activity = received(&activity);
+            let tasks = SendActivityTask::prepare_forward(
+                &received_activity,
+                &*DB_RELAY,
+                vec![inbox.clone()],
+                &data,
+            )
+            .await?;
+            assert_eq!(tasks.len(), 1);
+            tasks[0].sign_and_send(&data).await?;
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+
+        // the receiver doesn't accept the activity from the relay without the actor's signature
+        let mut unsigned = signed_follow();
+        unsigned.as_object_mut().unwrap().remove("signature");
+        let task = SendActivityTask {
+            actor_id: DB_RELAY.federation_id.clone(),
+            activity_id: &"https://localhost/123/follow/1".parse()?,
+            activity: serde_json::to_vec(&unsigned)?.into(),
+            inbox,
+            signer: Arc::new(PemSigner::new(&DB_RELAY_KEYPAIR.private_key)?),
+            http_signature_compat: false,
+        };
+        task.sign_and_send(&data).await?;
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
 }
@@ -1,10 +1,10 @@
 //! Handles incoming activities, verifying HTTP signatures and other checks
 
 use crate::{
-    config::Data,
+    config::{Data, ReceivedActivity},
     error::Error,
     fetch::object_id::ObjectId,
-    http_signatures::{verify_body_hash, verify_signature},
+    http_signatures::{verify_body_hash, verify_inbox_signature},
     traits::{ActivityHandler, Actor, Object},
 };
 use actix_web::{web::Bytes, HttpRequest, HttpResponse};
@@ -31,7 +31,11 @@ where
     <ActorT as Object>::Error: From<Error> + From<anyhow::Error>,
     Datatype: Clone,
 {
-    verify_body_hash(request.headers().get("Digest"), &body)?;
+    verify_body_hash(
+        request.headers().get("Digest"),
+        request.headers().get("Content-Digest"),
+        &body,
+    )?;
 
     let activity: Activity = serde_json::from_slice(&body)
         .with_context(|| format!("deserializing body: {}", String::from_utf8_lossy(&body)))?;
@@ -40,16 +44,30 @@ where
         .dereference(data)
         .await?;
 
-    verify_signature(
+    verify_inbox_signature(
         request.headers(),
         request.method(),
         request.uri(),
-        actor.public_key_pem(),
-    )?;
+        &body,
+        &actor,
+        data,
+    )
+    .await?;
+
+    let data = data.with_received_activity(ReceivedActivity {
+        id: activity.id().clone(),
+        actor: activity.actor().clone(),
+        headers: request
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+        body,
+    });
 
     debug!("Receiving activity {}", activity.id().to_string());
-    activity.verify(data).await?;
-    activity.receive(data).await?;
+    activity.verify(&data).await?;
+    activity.receive(&data).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -59,8 +77,27 @@ mod test {
     use crate::{
         activity_sending::generate_request_headers,
         config::FederationConfig,
-        http_signatures::sign_request,
-        traits::tests::{DbConnection, DbUser, Follow, DB_USER_KEYPAIR},
+        http_signatures::{
+            generate_actor_keypair,
+            sign_request,
+            sign_request_with_scheme,
+            BodyDigest,
+            HttpSignatureScheme,
+            Keypair,
+            PemSigner,
+        },
+        protocol::{integrity_proof::add_proof, ld_signature::sign_ld},
+        traits::tests::{
+            DbConnection,
+            DbUser,
+            Follow,
+            ForwardableFollow,
+            DB_RELAY,
+            DB_RELAY_KEYPAIR,
+            DB_USER,
+            DB_USER_ED25519_KEYPAIR,
+            DB_USER_KEYPAIR,
+        },
     };
     use actix_web::test::TestRequest;
     use reqwest::Client;
@@ -79,6 +116,32 @@ mod test {
         .unwrap();
     }
 
+    #[tokio::test]
+    async fn test_receive_activity_headers() {
+        // the raw activity with its signature headers is available to handlers for forwarding
+        let (body, incoming_request, config) = setup_receive_test().await;
+        receive_activity::<ForwardableFollow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body,
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_receive_activity_rfc9421() {
+        let (body, incoming_request, config) =
+            setup_receive_test_with_scheme(HttpSignatureScheme::Rfc9421).await;
+        receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body,
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+    }
+
     #[tokio::test]
     async fn test_receive_activity_invalid_body_signature() {
         let (_, incoming_request, config) = setup_receive_test().await;
@@ -112,7 +175,157 @@ mod test {
         assert_eq!(e, &Error::ActivitySignatureInvalid)
     }
 
+    #[tokio::test]
+    async fn test_receive_activity_ld_signature() {
+        // activities which are signed by the actor can be delivered by another server, if that
+        // server has a valid HTTP signature itself
+        let mut activity = follow_json();
+        sign_ld(
+            &mut activity,
+            &DB_USER.federation_id,
+            &DB_USER_KEYPAIR.private_key,
+        );
+        let body: Bytes = serde_json::to_vec(&activity).unwrap().into();
+        let incoming_request =
+            signed_incoming_request(body.clone(), &DB_RELAY.federation_id, &DB_RELAY_KEYPAIR).await;
+        let (_, _, config) = setup_receive_test().await;
+
+        receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body,
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+
+        // without the LD signature, the relay can't deliver activities of other actors
+        activity.as_object_mut().unwrap().remove("signature");
+        let body: Bytes = serde_json::to_vec(&activity).unwrap().into();
+        let incoming_request =
+            signed_incoming_request(body.clone(), &DB_RELAY.federation_id, &DB_RELAY_KEYPAIR).await;
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body,
+            &config.to_request_data(),
+        )
+        .await
+        .err()
+        .unwrap();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::ActivitySignatureInvalid)
+    }
+
+    #[tokio::test]
+    async fn test_receive_activity_ld_signature_invalid_deliverer() {
+        // the LD signature from the actor is only accepted if the delivering server has a valid
+        // HTTP signature itself
+        let mut activity = follow_json();
+        sign_ld(
+            &mut activity,
+            &DB_USER.federation_id,
+            &DB_USER_KEYPAIR.private_key,
+        );
+        let body: Bytes = serde_json::to_vec(&activity).unwrap().into();
+        let other_keypair = generate_actor_keypair().unwrap();
+        let incoming_request =
+            signed_incoming_request(body.clone(), &DB_USER.federation_id, &other_keypair).await;
+        let (_, _, config) = setup_receive_test().await;
+
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body,
+            &config.to_request_data(),
+        )
+        .await
+        .err()
+        .unwrap();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::ActivitySignatureInvalid)
+    }
+
+    #[tokio::test]
+    async fn test_receive_activity_integrity_proof() {
+        // activities with a valid integrity proof are accepted from any deliverer
+        let mut activity = follow_json();
+        add_proof(
+            &mut activity,
+            &DB_USER.federation_id,
+            &DB_USER_ED25519_KEYPAIR.private_key,
+        )
+        .unwrap();
+        let body: Bytes = serde_json::to_vec(&activity).unwrap().into();
+        let other_keypair = generate_actor_keypair().unwrap();
+        let incoming_request =
+            signed_incoming_request(body.clone(), &DB_USER.federation_id, &other_keypair).await;
+        let (_, _, config) = setup_receive_test().await;
+
+        receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body,
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+
+        activity["object"] = "http://localhost:125".into();
+        let body: Bytes = serde_json::to_vec(&activity).unwrap().into();
+        let incoming_request =
+            signed_incoming_request(body.clone(), &DB_USER.federation_id, &other_keypair).await;
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body,
+            &config.to_request_data(),
+        )
+        .await
+        .err()
+        .unwrap();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::ActivitySignatureInvalid)
+    }
+
+    fn follow_json() -> serde_json::Value {
+        serde_json::to_value(Follow {
+            actor: ObjectId::parse("https://localhost/123").unwrap(),
+            object: ObjectId::parse("http://localhost:124").unwrap(),
+            kind: Default::default(),
+            id: "https://localhost/123/1".try_into().unwrap(),
+        })
+        .unwrap()
+    }
+
+    async fn signed_incoming_request(
+        body: Bytes,
+        actor_id: &Url,
+        keypair: &Keypair,
+    ) -> TestRequest {
+        let inbox = "https://example.com/inbox";
+        let request_builder = ClientWithMiddleware::from(Client::default())
+            .post(inbox)
+            .headers(generate_request_headers(&Url::parse(inbox).unwrap()));
+        let outgoing_request = sign_request(
+            request_builder,
+            actor_id,
+            body,
+            &PemSigner::new(&keypair.private_key).unwrap(),
+            false,
+            BodyDigest::default(),
+        )
+        .await
+        .unwrap();
+        let mut incoming_request = TestRequest::post().uri(outgoing_request.url().path());
+        for h in outgoing_request.headers() {
+            incoming_request = incoming_request.append_header(h);
+        }
+        incoming_request
+    }
+
     async fn setup_receive_test() -> (Bytes, TestRequest, FederationConfig<DbConnection>) {
+        setup_receive_test_with_scheme(HttpSignatureScheme::Cavage).await
+    }
+
+    async fn setup_receive_test_with_scheme(
+        scheme: HttpSignatureScheme,
+    ) -> (Bytes, TestRequest, FederationConfig<DbConnection>) {
         let inbox = "https://example.com/inbox";
         let headers = generate_request_headers(&Url::parse(inbox).unwrap());
         let request_builder = ClientWithMiddleware::from(Client::default())
@@ -125,12 +338,14 @@ mod test {
             id: "http://localhost:123/1".try_into().unwrap(),
         };
         let body: Bytes = serde_json::to_vec(&activity).unwrap().into();
-        let outgoing_request = sign_request(
+        let outgoing_request = sign_request_with_scheme(
+            scheme,
             request_builder,
             &activity.actor.into_inner(),
             body.clone(),
-            DB_USER_KEYPAIR.private_key().unwrap(),
+            &PemSigner::new(&DB_USER_KEYPAIR.private_key).unwrap(),
             false,
+            BodyDigest::default(),
         )
         .await
         .unwrap();
@@ -1,12 +1,22 @@
-use crate::config::{Data, FederationConfig, FederationMiddleware};
+use crate::{
+    config::{AuthorizedFetchMiddleware, Data, FederationConfig, FederationMiddleware},
+    traits::{Actor, Object},
+};
 use actix_web::{
+    body::EitherBody,
     dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
     Error,
     FromRequest,
     HttpMessage,
     HttpRequest,
+    HttpResponse,
+};
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
 };
-use std::future::{ready, Ready};
 
 impl<S, B, T> Transform<S, ServiceRequest> for FederationMiddleware<T>
 where
@@ -74,3 +84,209 @@ impl<T: Clone + 'static> FromRequest for Data<T> {
         })
     }
 }
+
+impl<S, B, A> Transform<S, ServiceRequest> for AuthorizedFetchMiddleware<A>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+    A: Object + Actor,
+    <A as Object>::DataType: Clone + Sync,
+    <A as Object>::Error: From<crate::error::Error> + From<anyhow::Error>,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2>,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = AuthorizedFetchService<S, A>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthorizedFetchService {
+            service: Rc::new(service),
+            middleware: self.clone(),
+        }))
+    }
+}
+
+/// Rejects requests without valid signature, see [AuthorizedFetchMiddleware]
+#[doc(hidden)]
+pub struct AuthorizedFetchService<S, A> {
+    service: Rc<S>,
+    middleware: AuthorizedFetchMiddleware<A>,
+}
+
+impl<S, B, A> Service<ServiceRequest> for AuthorizedFetchService<S, A>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+    A: Object + Actor,
+    <A as Object>::DataType: Clone + Sync,
+    <A as Object>::Error: From<crate::error::Error> + From<anyhow::Error>,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2>,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let middleware = self.middleware.clone();
+        Box::pin(async move {
+            let Some(config) = req
+                .extensions()
+                .get::<FederationConfig<<A as Object>::DataType>>()
+                .cloned()
+            else {
+                return Err(actix_web::error::ErrorInternalServerError(
+                    "Missing extension, did you register FederationMiddleware?",
+                ));
+            };
+            let data = config.to_request_data();
+            match middleware
+                .verify(req.headers(), req.method(), req.uri(), &data)
+                .await
+            {
+                Ok(Some(actor)) => {
+                    req.extensions_mut().insert(actor);
+                }
+                Ok(None) => {}
+                Err(status) => {
+                    return Ok(req
+                        .into_response(HttpResponse::new(status))
+                        .map_into_right_body())
+                }
+            }
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        activity_sending::generate_request_headers,
+        config::UrlVerifier,
+        http_signatures::{sign_request, BodyDigest, PemSigner},
+        traits::tests::{DbConnection, DbUser, DB_USER_KEYPAIR},
+    };
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, TestRequest},
+        web,
+        App,
+    };
+    use async_trait::async_trait;
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+    use url::Url;
+
+    #[derive(Clone)]
+    struct BlockAllVerifier;
+
+    #[async_trait]
+    impl UrlVerifier for BlockAllVerifier {
+        async fn verify(&self, _url: &Url) -> Result<(), anyhow::Error> {
+            Err(anyhow::anyhow!("blocked"))
+        }
+    }
+
+    async fn signed_request(path: &str) -> TestRequest {
+        let url = Url::parse(&format!("https://example.com{path}")).unwrap();
+        let request_builder = ClientWithMiddleware::from(Client::default())
+            .get(url.as_str())
+            .headers(generate_request_headers(&url));
+        let outgoing_request = sign_request(
+            request_builder,
+            &Url::parse("http://localhost:123").unwrap(),
+            Default::default(),
+            &PemSigner::new(&DB_USER_KEYPAIR.private_key).unwrap(),
+            false,
+            BodyDigest::default(),
+        )
+        .await
+        .unwrap();
+        let mut incoming_request = TestRequest::get().uri(path);
+        for h in outgoing_request.headers() {
+            incoming_request = incoming_request.append_header(h);
+        }
+        incoming_request
+    }
+
+    async fn status(config: FederationConfig<DbConnection>, request: TestRequest) -> StatusCode {
+        let app = init_service(
+            App::new()
+                .wrap(
+                    AuthorizedFetchMiddleware::<DbUser>::new()
+                        .allow_unsigned_path("/.well-known/webfinger")
+                        .allow_unsigned_path("/nodeinfo/*"),
+                )
+                .wrap(FederationMiddleware::new(config))
+                .default_service(web::to(|request: HttpRequest| async move {
+                    // the signing actor is available to handlers
+                    match request.extensions().get::<DbUser>() {
+                        Some(_) => HttpResponse::Ok().finish(),
+                        None => HttpResponse::NoContent().finish(),
+                    }
+                })),
+        )
+        .await;
+        call_service(&app, request.to_request()).await.status()
+    }
+
+    #[tokio::test]
+    async fn test_authorized_fetch() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .await
+            .unwrap();
+
+        let unsigned = TestRequest::get().uri("/u/alice");
+        assert_eq!(
+            status(config.clone(), unsigned).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let signed = signed_request("/u/alice").await;
+        assert_eq!(status(config.clone(), signed).await, StatusCode::OK);
+        let wrong_path = signed_request("/u/alice").await.uri("/u/bob");
+        assert_eq!(
+            status(config.clone(), wrong_path).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        for path in ["/.well-known/webfinger", "/nodeinfo/2.1"] {
+            let unsigned = TestRequest::get().uri(path);
+            assert_eq!(
+                status(config.clone(), unsigned).await,
+                StatusCode::NO_CONTENT
+            );
+        }
+        let post = TestRequest::post().uri("/u/alice/inbox");
+        assert_eq!(status(config.clone(), post).await, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_authorized_fetch_blocked_domain() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .debug(true)
+            .url_verifier(Box::new(BlockAllVerifier))
+            .build()
+            .await
+            .unwrap();
+
+        let signed = signed_request("/u/alice").await;
+        assert_eq!(status(config, signed).await, StatusCode::FORBIDDEN);
+    }
+}
@@ -3,10 +3,10 @@
 #![doc = include_str!("../../docs/08_receiving_activities.md")]
 
 use crate::{
-    config::Data,
+    config::{Data, ReceivedActivity},
     error::Error,
     fetch::object_id::ObjectId,
-    http_signatures::{verify_body_hash, verify_signature},
+    http_signatures::{verify_body_hash, verify_inbox_signature},
     traits::{ActivityHandler, Actor, Object},
 };
 use axum::{
@@ -36,7 +36,11 @@ where
     <ActorT as Object>::Error: From<Error> + From<anyhow::Error>,
     Datatype: Clone,
 {
-    verify_body_hash(activity_data.headers.get("Digest"), &activity_data.body)?;
+    verify_body_hash(
+        activity_data.headers.get("Digest"),
+        activity_data.headers.get("Content-Digest"),
+        &activity_data.body,
+    )?;
 
     let activity: Activity = serde_json::from_slice(&activity_data.body)?;
     data.config.verify_url_and_domain(&activity).await?;
@@ -44,16 +48,26 @@ where
         .dereference(data)
         .await?;
 
-    verify_signature(
+    verify_inbox_signature(
         &activity_data.headers,
         &activity_data.method,
         &activity_data.uri,
-        actor.public_key_pem(),
-    )?;
+        &activity_data.body,
+        &actor,
+        data,
+    )
+    .await?;
+
+    let data = data.with_received_activity(ReceivedActivity {
+        id: activity.id().clone(),
+        actor: activity.actor().clone(),
+        headers: activity_data.headers,
+        body: activity_data.body.into(),
+    });
 
     debug!("Receiving activity {}", activity.id().to_string());
-    activity.verify(data).await?;
-    activity.receive(data).await?;
+    activity.verify(&data).await?;
+    activity.receive(&data).await?;
     Ok(())
 }
 
@@ -1,6 +1,17 @@
-use crate::config::{Data, FederationConfig, FederationMiddleware};
-use axum::{async_trait, body::Body, extract::FromRequestParts, http::Request, response::Response};
+use crate::{
+    config::{AuthorizedFetchMiddleware, Data, FederationConfig, FederationMiddleware},
+    traits::{Actor, Object},
+};
+use axum::{
+    async_trait,
+    body::Body,
+    extract::FromRequestParts,
+    http::Request,
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
 use http::{request::Parts, StatusCode};
+use serde::Deserialize;
 use std::task::{Context, Poll};
 use tower::{Layer, Service};
 
@@ -61,3 +72,196 @@ where
         }
     }
 }
+
+impl<S, A> Layer<S> for AuthorizedFetchMiddleware<A> {
+    type Service = AuthorizedFetchService<S, A>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthorizedFetchService {
+            inner,
+            middleware: self.clone(),
+        }
+    }
+}
+
+/// Rejects requests without valid signature, see [AuthorizedFetchMiddleware]
+#[doc(hidden)]
+pub struct AuthorizedFetchService<S, A> {
+    inner: S,
+    middleware: AuthorizedFetchMiddleware<A>,
+}
+
+impl<S: Clone, A> Clone for AuthorizedFetchService<S, A> {
+    fn clone(&self) -> Self {
+        AuthorizedFetchService {
+            inner: self.inner.clone(),
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+impl<S, A> Service<Request<Body>> for AuthorizedFetchService<S, A>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    A: Object + Actor + Clone + Sync,
+    <A as Object>::DataType: Clone + Send + Sync,
+    <A as Object>::Error: From<crate::error::Error> + From<anyhow::Error> + Send,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2> + Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        // use the service which was checked for readiness, and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let middleware = self.middleware.clone();
+        Box::pin(async move {
+            let Some(config) = request
+                .extensions()
+                .get::<FederationConfig<<A as Object>::DataType>>()
+                .cloned()
+            else {
+                return Ok((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Missing extension, did you register FederationMiddleware?",
+                )
+                    .into_response());
+            };
+            let data = config.to_request_data();
+            match middleware
+                .verify(request.headers(), request.method(), request.uri(), &data)
+                .await
+            {
+                Ok(Some(actor)) => {
+                    request.extensions_mut().insert(actor);
+                }
+                Ok(None) => {}
+                Err(status) => return Ok(status.into_response()),
+            }
+            inner.call(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        activity_sending::generate_request_headers,
+        config::UrlVerifier,
+        http_signatures::{sign_request, BodyDigest, PemSigner},
+        traits::tests::{DbConnection, DbUser, DB_USER_KEYPAIR},
+    };
+    use axum::Router;
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+    use tower::ServiceExt;
+    use url::Url;
+
+    #[derive(Clone)]
+    struct BlockAllVerifier;
+
+    #[async_trait]
+    impl UrlVerifier for BlockAllVerifier {
+        async fn verify(&self, _url: &Url) -> Result<(), anyhow::Error> {
+            Err(anyhow::anyhow!("blocked"))
+        }
+    }
+
+    async fn signed_request(path: &str) -> Request<Body> {
+        let url = Url::parse(&format!("https://example.com{path}")).unwrap();
+        let request_builder = ClientWithMiddleware::from(Client::default())
+            .get(url.as_str())
+            .headers(generate_request_headers(&url));
+        let outgoing_request = sign_request(
+            request_builder,
+            &Url::parse("http://localhost:123").unwrap(),
+            Default::default(),
+            &PemSigner::new(&DB_USER_KEYPAIR.private_key).unwrap(),
+            false,
+            BodyDigest::default(),
+        )
+        .await
+        .unwrap();
+        let mut request = Request::get(path);
+        for (name, value) in outgoing_request.headers() {
+            request = request.header(name, value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    async fn status(config: FederationConfig<DbConnection>, request: Request<Body>) -> StatusCode {
+        let app = Router::new()
+            .fallback(|request: Request<Body>| async move {
+                // the signing actor is available to handlers
+                match request.extensions().get::<DbUser>() {
+                    Some(_) => StatusCode::OK,
+                    None => StatusCode::NO_CONTENT,
+                }
+            })
+            .layer(
+                AuthorizedFetchMiddleware::<DbUser>::new()
+                    .allow_unsigned_path("/.well-known/webfinger")
+                    .allow_unsigned_path("/nodeinfo/*"),
+            )
+            .layer(FederationMiddleware::new(config));
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_authorized_fetch() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .await
+            .unwrap();
+
+        let unsigned = Request::get("/u/alice").body(Body::empty()).unwrap();
+        assert_eq!(
+            status(config.clone(), unsigned).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let signed = signed_request("/u/alice").await;
+        assert_eq!(status(config.clone(), signed).await, StatusCode::OK);
+        let mut wrong_path = signed_request("/u/alice").await;
+        *wrong_path.uri_mut() = "/u/bob".parse().unwrap();
+        assert_eq!(
+            status(config.clone(), wrong_path).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        for path in ["/.well-known/webfinger", "/nodeinfo/2.1"] {
+            let unsigned = Request::get(path).body(Body::empty()).unwrap();
+            assert_eq!(
+                status(config.clone(), unsigned).await,
+                StatusCode::NO_CONTENT
+            );
+        }
+        let post = Request::post("/u/alice/inbox").body(Body::empty()).unwrap();
+        assert_eq!(status(config.clone(), post).await, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_authorized_fetch_blocked_domain() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .debug(true)
+            .url_verifier(Box::new(BlockAllVerifier))
+            .build()
+            .await
+            .unwrap();
+
+        let signed = signed_request("/u/alice").await;
+        assert_eq!(status(config, signed).await, StatusCode::FORBIDDEN);
+    }
+}
@@ -6,3 +6,4 @@ pub mod inbox;
 pub mod json;
 #[doc(hidden)]
 pub mod middleware;
+pub mod signed_by;
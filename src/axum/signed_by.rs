@@ -0,0 +1,241 @@
+//! Extractors for the actor which signed an incoming request
+//!
+//! ```
+//! # use activitypub_federation::axum::signed_by::{MaybeSignedBy, SignedBy};
+//! # use activitypub_federation::traits::tests::DbUser;
+//! /// Only responds to signed requests, for example when authorized fetch is enabled
+//! async fn http_get_post(SignedBy(actor): SignedBy<DbUser>) -> String {
+//!     format!("Fetched by {}", actor.name)
+//! }
+//!
+//! /// Responds to all requests, but may hide some data if the request is unsigned
+//! async fn http_get_user(MaybeSignedBy(actor): MaybeSignedBy<DbUser>) -> String {
+//!     match actor {
+//!         Some(actor) => format!("Fetched by {}", actor.name),
+//!         None => "Anonymous fetch".to_string(),
+//!     }
+//! }
+//! ```
+
+use crate::{
+    config::FederationConfig,
+    error::Error,
+    http_signatures::{signing_actor_status, verify_body_hash},
+    traits::{Actor, Object},
+};
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::FromRequest,
+    http::Request,
+};
+use http::{request::Parts, StatusCode};
+use serde::Deserialize;
+
+/// Verifies the HTTP signature of a request, and returns the actor which signed it.
+///
+/// If the request has a body, its digest is verified as well. The body is consumed in this
+/// case, so this needs to be the last extractor of the handler. If the actor was already verified
+/// by [AuthorizedFetchMiddleware](crate::config::AuthorizedFetchMiddleware), it is reused.
+///
+/// Rejects the request with:
+/// - `401 Unauthorized` if there is no signature, or if it is invalid
+/// - `400 Bad Request` if the body doesn't match the digest
+/// - `403 Forbidden` if the actor's domain is rejected by the
+///   [UrlVerifier](crate::config::UrlVerifier)
+#[derive(Clone, Debug)]
+pub struct SignedBy<A>(pub A);
+
+/// Same as [SignedBy], but returns `None` for unsigned requests instead of rejecting them.
+///
+/// Requests which have a signature are still rejected if it is invalid.
+#[derive(Clone, Debug)]
+pub struct MaybeSignedBy<A>(pub Option<A>);
+
+#[async_trait]
+impl<S, B, A> FromRequest<S, B> for SignedBy<A>
+where
+    B: HttpBody + Send + 'static,
+    S: Send + Sync,
+    <B as HttpBody>::Error: std::fmt::Display,
+    <B as HttpBody>::Data: Send,
+    A: Object + Actor + Clone + Sync,
+    <A as Object>::DataType: Clone + Send + Sync,
+    <A as Object>::Error: From<Error> + From<anyhow::Error> + Send,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2> + Send,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: Request<B>, _state: &S) -> Result<Self, Self::Rejection> {
+        let (parts, body) = req.into_parts();
+        if let Some(actor) = parts.extensions.get::<A>() {
+            return Ok(SignedBy(actor.clone()));
+        }
+        // this wont work if the body is an long running stream
+        let body = hyper::body::to_bytes(body)
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to read request body"))?;
+        signed_by(&parts, &body).await.map(SignedBy)
+    }
+}
+
+#[async_trait]
+impl<S, B, A> FromRequest<S, B> for MaybeSignedBy<A>
+where
+    B: HttpBody + Send + 'static,
+    S: Send + Sync,
+    <B as HttpBody>::Error: std::fmt::Display,
+    <B as HttpBody>::Data: Send,
+    A: Object + Actor + Clone + Sync,
+    <A as Object>::DataType: Clone + Send + Sync,
+    <A as Object>::Error: From<Error> + From<anyhow::Error> + Send,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2> + Send,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = req.headers();
+        if !headers.contains_key("signature") && !headers.contains_key("signature-input") {
+            return Ok(MaybeSignedBy(None));
+        }
+        let SignedBy(actor) = SignedBy::from_request(req, state).await?;
+        Ok(MaybeSignedBy(Some(actor)))
+    }
+}
+
+async fn signed_by<A>(parts: &Parts, body: &Bytes) -> Result<A, (StatusCode, &'static str)>
+where
+    A: Object + Actor,
+    <A as Object>::DataType: Clone,
+    <A as Object>::Error: From<Error> + From<anyhow::Error>,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2>,
+{
+    let data = match parts
+        .extensions
+        .get::<FederationConfig<<A as Object>::DataType>>()
+    {
+        Some(config) => config.to_request_data(),
+        None => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Missing extension, did you register FederationMiddleware?",
+            ))
+        }
+    };
+    if !body.is_empty() {
+        verify_body_hash(
+            parts.headers.get("Digest"),
+            parts.headers.get("Content-Digest"),
+            body,
+        )
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid body digest"))?;
+    }
+    signing_actor_status(&parts.headers, &parts.method, &parts.uri, &data)
+        .await
+        .map_err(|status| match status {
+            StatusCode::FORBIDDEN => (status, "Signing actor is blocked"),
+            _ => (status, "Invalid HTTP signature"),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        activity_sending::generate_request_headers,
+        config::FederationMiddleware,
+        http_signatures::{sign_request, BodyDigest, PemSigner},
+        traits::tests::{DbConnection, DbUser, DB_USER_KEYPAIR},
+    };
+    use axum::{body::Body, routing::get, Router};
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+    use tower::ServiceExt;
+    use url::Url;
+
+    async fn signed(SignedBy(actor): SignedBy<DbUser>) -> String {
+        actor.federation_id.to_string()
+    }
+
+    async fn maybe_signed(MaybeSignedBy(actor): MaybeSignedBy<DbUser>) -> String {
+        actor
+            .map(|a| a.federation_id.to_string())
+            .unwrap_or_default()
+    }
+
+    async fn call(request: Request<Body>) -> (StatusCode, String) {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .await
+            .unwrap();
+        let app = Router::new()
+            .route("/signed", get(signed).post(signed))
+            .route("/maybe_signed", get(maybe_signed))
+            .layer(FederationMiddleware::new(config));
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn signed_request(path: &str, body: &'static str) -> Request<Body> {
+        let url = Url::parse(&format!("https://example.com{path}")).unwrap();
+        let client = ClientWithMiddleware::from(Client::default());
+        let request_builder = match body {
+            "" => client.get(url.as_str()),
+            _ => client.post(url.as_str()),
+        };
+        let outgoing_request = sign_request(
+            request_builder.headers(generate_request_headers(&url)),
+            &Url::parse("http://localhost:123").unwrap(),
+            body.into(),
+            &PemSigner::new(&DB_USER_KEYPAIR.private_key).unwrap(),
+            false,
+            BodyDigest::default(),
+        )
+        .await
+        .unwrap();
+        let mut request = Request::builder()
+            .method(outgoing_request.method())
+            .uri(path);
+        for (name, value) in outgoing_request.headers() {
+            request = request.header(name, value);
+        }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_signed_by() {
+        let (status, body) = call(signed_request("/signed", "").await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "https://localhost/123");
+
+        let (status, _) = call(signed_request("/signed", "my activity").await).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let unsigned = Request::get("/signed").body(Body::empty()).unwrap();
+        assert_eq!(call(unsigned).await.0, StatusCode::UNAUTHORIZED);
+
+        let (parts, _) = signed_request("/signed", "my activity").await.into_parts();
+        let modified_body = Request::from_parts(parts, Body::from("other activity"));
+        assert_eq!(call(modified_body).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_maybe_signed_by() {
+        let (status, body) = call(signed_request("/maybe_signed", "").await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "https://localhost/123");
+
+        let unsigned = Request::get("/maybe_signed").body(Body::empty()).unwrap();
+        assert_eq!(call(unsigned).await, (StatusCode::OK, String::new()));
+
+        let (parts, _) = signed_request("/signed", "").await.into_parts();
+        let mut invalid = Request::from_parts(parts, Body::empty());
+        *invalid.uri_mut() = "/maybe_signed".parse().unwrap();
+        assert_eq!(call(invalid).await.0, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -14,20 +14,44 @@
 //! # }).unwrap()
 //! ```
 
+#[cfg(feature = "ssrf")]
+use crate::ssrf::SsrfGuard;
 use crate::{
     error::Error,
+    fetch::{
+        default_accepted_content_types,
+        negative_cache::NegativeCache,
+        object_id::{BackgroundRefresh, InFlightFetches},
+        FetchBudget,
+        RedirectPolicy,
+        ResponseSizeLimits,
+    },
+    http_signatures::{
+        signing_actor_status,
+        BodyDigest,
+        HttpSignatureScheme,
+        PemSigner,
+        SignatureStrategy,
+        Signer,
+    },
     protocol::verification::verify_domains_match,
-    traits::{ActivityHandler, Actor},
+    traits::{ActivityHandler, Actor, Object},
 };
 use anyhow::anyhow;
 use async_trait::async_trait;
+use bytes::Bytes;
 use derive_builder::Builder;
 use dyn_clone::{clone_trait_object, DynClone};
+use http::{header::HeaderName, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use ipnet::IpNet;
 use moka::future::Cache;
-use openssl::pkey::{PKey, Private};
+use openssl::pkey::{PKey, Private, Public};
+use reqwest::redirect::Policy;
 use reqwest_middleware::ClientWithMiddleware;
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize};
 use std::{
+    marker::PhantomData,
+    net::IpAddr,
     ops::Deref,
     sync::{
         atomic::{AtomicU32, Ordering},
@@ -35,7 +59,7 @@ use std::{
     },
     time::Duration,
 };
-use url::Url;
+use url::{Host, Url};
 
 /// Configuration for this library, with various federation related settings
 #[derive(Builder, Clone)]
@@ -51,10 +75,43 @@ pub struct FederationConfig<T: Clone> {
     /// [crate::fetch::object_id::ObjectId] for more details.
     #[builder(default = "20")]
     pub(crate) http_fetch_limit: u32,
-    #[builder(default = "reqwest::Client::default().into()")]
+    /// Maximum number of nested objects which are dereferenced while verifying and parsing a
+    /// fetched object, for example the parents of a reply. See
+    /// [crate::fetch::object_id::ObjectId] for more details.
+    #[builder(default = "10")]
+    pub(crate) dereference_depth_limit: u32,
+    /// Maximum number of objects which are fetched at the same time by
+    /// [ObjectId::dereference_many](crate::fetch::object_id::ObjectId::dereference_many).
+    #[builder(default = "10")]
+    pub(crate) batch_fetch_concurrency: usize,
+    /// Maximum size of response bodies for each kind of request. Can be changed for a single
+    /// call with [Data::with_response_size_limits].
+    #[builder(default)]
+    pub(crate) response_size_limits: ResponseSizeLimits,
+    /// Media types which are accepted in the `Content-Type` of fetched objects, other responses
+    /// fail with [Error::UnexpectedContentType]. Parameters such as `profile` are ignored.
+    #[builder(default = "default_accepted_content_types()")]
+    pub(crate) accepted_content_types: Vec<String>,
+    #[builder(default = "self.default_client()")]
     /// HTTP client used for all outgoing requests. Middleware can be used to add functionality
     /// like log tracing or retry of failed requests.
+    ///
+    /// With the `ssrf` feature, the default client only connects to public IP addresses, also in
+    /// debug mode. Use [SsrfGuard](crate::ssrf::SsrfGuard) to keep this protection for a custom
+    /// client.
     pub(crate) client: ClientWithMiddleware,
+    /// Check that objects fetched over HTTP have the requested URL as id, and refetch them from
+    /// their canonical id if not. See [fetch_object_http](crate::fetch::fetch_object_http).
+    #[builder(default = "true")]
+    pub(crate) check_fetched_id: bool,
+    /// Which redirects are followed when fetching remote data, see [RedirectPolicy].
+    #[builder(default)]
+    pub(crate) redirect_policy: RedirectPolicy,
+    /// Networks which the default client may connect to, even though they are not public. This
+    /// is mainly useful for tests, which need to allow loopback addresses to reach local
+    /// servers. See [SsrfGuard](crate::ssrf::SsrfGuard).
+    #[builder(default)]
+    pub(crate) ssrf_allowlist: Vec<IpNet>,
     /// Run library in debug mode. This allows usage of http and localhost urls. It also sends
     /// outgoing activities synchronously, not in background thread. This helps to make tests
     /// more consistent. Do not use for production.
@@ -67,6 +124,11 @@ pub struct FederationConfig<T: Clone> {
     /// use the same as timeout when sending
     #[builder(default = "Duration::from_secs(10)")]
     pub(crate) request_timeout: Duration,
+    /// How long remote objects are considered fresh after they were last refreshed, unless
+    /// [Object::refetch_interval](crate::traits::Object::refetch_interval) is set. This is the
+    /// same in debug and release builds, so set a shorter interval explicitly for development.
+    #[builder(default = "Duration::from_secs(24 * 60 * 60)")]
+    pub(crate) refetch_interval: Duration,
     /// Function used to verify that urls are valid, See [UrlVerifier] for details.
     #[builder(default = "Box::new(DefaultUrlVerifier())")]
     pub(crate) url_verifier: Box<dyn UrlVerifier + Sync>,
@@ -74,18 +136,65 @@ pub struct FederationConfig<T: Clone> {
     /// (expires) fields. This is required for compatibility with some software like Pleroma.
     /// <https://datatracker.ietf.org/doc/html/draft-cavage-http-signatures-10>
     /// <https://git.pleroma.social/pleroma/pleroma/-/issues/2939>
+    ///
+    /// If this is disabled, compatibility mode is still used for individual hosts which reject
+    /// standard signatures, see [SignatureCompatStore].
     #[builder(default = "false")]
     pub(crate) http_signature_compat: bool,
+    /// Remembers which remote hosts require signatures in compatibility mode. See
+    /// [SignatureCompatStore] for details.
+    #[builder(default = "Box::new(InMemorySignatureCompatStore::default())")]
+    pub(crate) signature_compat_store: Box<dyn SignatureCompatStore + Sync>,
+    /// Header and hash algorithm used for the digest of outgoing request bodies. Defaults to the
+    /// `Digest` header with SHA-256.
+    #[builder(default)]
+    pub(crate) body_digest: BodyDigest,
+    /// Which HTTP signature standard to use for outgoing requests, see [SignatureStrategy].
+    #[builder(default = "SignatureStrategy::Cavage")]
+    pub(crate) signature_strategy: SignatureStrategy,
+    /// Signature scheme which was accepted by each remote host, when using
+    /// [SignatureStrategy::DoubleKnock]. Entries expire after one day, so that hosts which add
+    /// support for RFC 9421 are eventually detected.
+    #[builder(
+        default = "Cache::builder().max_capacity(10000).time_to_live(Duration::from_secs(24 * 60 * 60)).build()",
+        setter(skip)
+    )]
+    pub(crate) signature_scheme_cache: Cache<String, HttpSignatureScheme>,
     /// Actor Id and private key to use to sign all federated fetch requests.
     /// This can be used to implement secure mode federation.
     /// <https://docs.joinmastodon.org/spec/activitypub/#secure-mode>
     #[builder(default = "None", setter(custom))]
-    pub(crate) signed_fetch_actor: Option<Arc<(Url, PKey<Private>)>>,
+    pub(crate) signed_fetch_actor: Option<Arc<(Url, Arc<dyn Signer>)>>,
     #[builder(
         default = "Cache::builder().max_capacity(10000).build()",
         setter(custom)
     )]
     pub(crate) actor_pkey_cache: Cache<Url, PKey<Private>>,
+    /// Parsed public keys of remote actors, by key id. Also contains a hash of the PEM data, so
+    /// that changed keys are detected.
+    #[builder(
+        default = "Cache::builder().max_capacity(10000).build()",
+        setter(custom)
+    )]
+    pub(crate) public_key_cache: Cache<String, ([u8; 32], PKey<Public>)>,
+    /// Refreshes of stale objects which are running in the background, see
+    /// [ObjectId::dereference_stale_while_revalidate](crate::fetch::object_id::ObjectId::dereference_stale_while_revalidate).
+    #[builder(default = "BackgroundRefresh::new(10)", setter(custom))]
+    pub(crate) background_refresh: BackgroundRefresh,
+    /// Urls which failed to fetch recently, see [NegativeCache].
+    #[builder(default)]
+    pub(crate) negative_cache: NegativeCache,
+    /// Canonical ids of objects which were fetched from another url, by that url. Dereferencing
+    /// the url again reads the object under its canonical id, instead of fetching it twice.
+    #[builder(
+        default = "Cache::builder().max_capacity(10000).time_to_live(Duration::from_secs(24 * 60 * 60)).build()",
+        setter(skip)
+    )]
+    pub(crate) canonical_ids: Cache<Url, Url>,
+    /// Dereferences which are currently running, so that concurrent dereferences of the same
+    /// url only make a single request.
+    #[builder(default, setter(skip))]
+    pub(crate) in_flight_fetches: InFlightFetches,
 }
 
 impl<T: Clone> FederationConfig<T> {
@@ -112,11 +221,28 @@ impl<T: Clone> FederationConfig<T> {
         Ok(())
     }
 
+    /// Urls which failed to fetch recently. Entries can be removed so that the url is fetched
+    /// again on the next dereference.
+    pub fn negative_cache(&self) -> &NegativeCache {
+        &self.negative_cache
+    }
+
+    /// Number of requests which [Data] may make. A request is only aborted once the counter
+    /// exceeds `http_fetch_limit`, so one more request than the limit is allowed.
+    fn request_limit(&self) -> u32 {
+        self.http_fetch_limit.saturating_add(1)
+    }
+
     /// Create new [Data] from this. You should prefer to use a middleware if possible.
     pub fn to_request_data(&self) -> Data<T> {
         Data {
             config: self.clone(),
             request_counter: Default::default(),
+            request_limit: self.request_limit(),
+            dereference_chain: vec![],
+            depth_limit: self.dereference_depth_limit,
+            response_size_limits: self.response_size_limits,
+            received_activity: None,
         }
     }
 
@@ -142,7 +268,15 @@ impl<T: Clone> FederationConfig<T> {
             return Ok(());
         }
 
-        if url.domain().is_none() {
+        // ip addresses are only allowed if they are explicitly permitted for tests
+        let ip = match url.host() {
+            Some(Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+            Some(Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+            _ => None,
+        };
+        let allowlisted_ip =
+            ip.is_some_and(|ip| self.ssrf_allowlist.iter().any(|net| net.contains(&ip)));
+        if url.domain().is_none() && !allowlisted_ip {
             return Err(Error::UrlVerificationError(anyhow!(
                 "Url must have a domain"
             )));
@@ -181,22 +315,59 @@ impl<T: Clone> FederationConfig<T> {
 impl<T: Clone> FederationConfigBuilder<T> {
     /// Sets an actor to use to sign all federated fetch requests
     pub fn signed_fetch_actor<A: Actor>(&mut self, actor: &A) -> &mut Self {
-        let private_key_pem = actor
-            .private_key_pem()
-            .expect("actor does not have a private key to sign with");
-
-        let private_key = PKey::private_key_from_pem(private_key_pem.as_bytes())
-            .expect("Could not decode PEM data");
-        self.signed_fetch_actor = Some(Some(Arc::new((actor.id(), private_key))));
+        let signer = actor.signer().unwrap_or_else(|| {
+            let private_key_pem = actor
+                .private_key_pem()
+                .expect("actor does not have a private key to sign with");
+            Arc::new(PemSigner::new(&private_key_pem).expect("Could not decode PEM data"))
+        });
+        self.signed_fetch_actor = Some(Some(Arc::new((actor.id(), signer))));
         self
     }
 
+    /// Client with [SsrfGuard], which doesn't follow redirects by itself. Local servers in tests
+    /// need to be added to [FederationConfigBuilder::ssrf_allowlist].
+    #[cfg(feature = "ssrf")]
+    fn default_client(&self) -> ClientWithMiddleware {
+        // redirects are handled in fetch_object_http according to the redirect policy
+        SsrfGuard::new(self.ssrf_allowlist.clone().unwrap_or_default())
+            .client_builder()
+            .redirect(Policy::none())
+            .build()
+            .expect("Failed to create HTTP client")
+            .into()
+    }
+
+    /// Client without SSRF protection, because the `ssrf` feature is disabled.
+    #[cfg(not(feature = "ssrf"))]
+    fn default_client(&self) -> ClientWithMiddleware {
+        reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()
+            .expect("Failed to create HTTP client")
+            .into()
+    }
+
     /// sets the number of parsed actor private keys to keep in memory
     pub fn actor_pkey_cache(&mut self, cache_size: u64) -> &mut Self {
         self.actor_pkey_cache = Some(Cache::builder().max_capacity(cache_size).build());
         self
     }
 
+    /// sets the number of parsed remote actor public keys to keep in memory
+    pub fn public_key_cache(&mut self, cache_size: u64) -> &mut Self {
+        self.public_key_cache = Some(Cache::builder().max_capacity(cache_size).build());
+        self
+    }
+
+    /// sets the maximum number of stale objects which are refreshed in the background at the
+    /// same time, see
+    /// [ObjectId::dereference_stale_while_revalidate](crate::fetch::object_id::ObjectId::dereference_stale_while_revalidate)
+    pub fn background_refresh_limit(&mut self, limit: usize) -> &mut Self {
+        self.background_refresh = Some(BackgroundRefresh::new(limit));
+        self
+    }
+
     /// Constructs a new config instance with the values supplied to builder.
     ///
     /// Values which are not explicitly specified use the defaults. Also initializes the
@@ -270,17 +441,96 @@ impl UrlVerifier for DefaultUrlVerifier {
 
 clone_trait_object!(UrlVerifier);
 
+/// Storage for hosts which require HTTP signatures in compatibility mode.
+///
+/// Outgoing requests are first signed with the standard configuration. If a host rejects this
+/// with `401 Unauthorized` because of the signature, the request is signed again in
+/// compatibility mode (see [FederationConfigBuilder::http_signature_compat]). When that
+/// succeeds, the host is stored with this trait so that compatibility mode is used directly for
+/// all further requests to it.
+///
+/// The default implementation keeps hosts in memory, so they need to be detected again after
+/// restart. Implement this trait to persist them in the database instead.
+///
+/// ```
+/// # use async_trait::async_trait;
+/// # use activitypub_federation::config::SignatureCompatStore;
+/// # #[derive(Clone)]
+/// # struct DatabaseConnection();
+/// # impl DatabaseConnection {
+/// #     async fn read_compat_host(&self, _host: &str) -> bool { false }
+/// #     async fn write_compat_host(&self, _host: &str) {}
+/// # }
+/// #[derive(Clone)]
+/// struct DatabaseCompatStore {
+///     db_connection: DatabaseConnection,
+/// }
+///
+/// #[async_trait]
+/// impl SignatureCompatStore for DatabaseCompatStore {
+///     async fn requires_compat(&self, host: &str) -> bool {
+///         self.db_connection.read_compat_host(host).await
+///     }
+///
+///     async fn set_requires_compat(&self, host: &str) {
+///         self.db_connection.write_compat_host(host).await
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait SignatureCompatStore: DynClone + Send {
+    /// Returns true if requests to `host` need to be signed in compatibility mode. The host
+    /// includes the port, if any.
+    async fn requires_compat(&self, host: &str) -> bool;
+
+    /// Called after `host` accepted a request in compatibility mode, which was previously
+    /// rejected with the standard signature.
+    async fn set_requires_compat(&self, host: &str);
+}
+
+/// Default [SignatureCompatStore] which keeps hosts in memory.
+#[derive(Clone)]
+struct InMemorySignatureCompatStore(Cache<String, ()>);
+
+impl Default for InMemorySignatureCompatStore {
+    fn default() -> Self {
+        InMemorySignatureCompatStore(Cache::builder().max_capacity(10000).build())
+    }
+}
+
+#[async_trait]
+impl SignatureCompatStore for InMemorySignatureCompatStore {
+    async fn requires_compat(&self, host: &str) -> bool {
+        self.0.contains_key(host)
+    }
+
+    async fn set_requires_compat(&self, host: &str) {
+        self.0.insert(host.to_string(), ()).await
+    }
+}
+
+clone_trait_object!(SignatureCompatStore);
+
 /// Stores data for handling one specific HTTP request.
 ///
 /// It gives acess to the `app_data` which was passed to [FederationConfig::builder].
 ///
-/// Additionally it contains a counter for outgoing HTTP requests. This is necessary to
-/// prevent denial of service attacks, where an attacker triggers fetching of recursive objects.
+/// Additionally it contains a counter for outgoing HTTP requests, and the chain of objects which
+/// are currently being dereferenced. This is necessary to prevent denial of service attacks,
+/// where an attacker triggers fetching of recursive objects.
 ///
 /// <https://www.w3.org/TR/activitypub/#security-recursive-objects>
 pub struct Data<T: Clone> {
     pub(crate) config: FederationConfig<T>,
-    pub(crate) request_counter: AtomicU32,
+    /// Shared by all nested data, so that requests for nested objects are counted as well
+    pub(crate) request_counter: Arc<AtomicU32>,
+    /// Total number of requests which may be made, further requests fail
+    pub(crate) request_limit: u32,
+    /// Urls of the objects which are currently being dereferenced, outermost first
+    pub(crate) dereference_chain: Vec<Url>,
+    pub(crate) depth_limit: u32,
+    pub(crate) response_size_limits: ResponseSizeLimits,
+    pub(crate) received_activity: Option<Arc<ReceivedActivity>>,
 }
 
 impl<T: Clone> Data<T> {
@@ -297,14 +547,104 @@ impl<T: Clone> Data<T> {
     /// Returns a new instance of `Data` with request counter set to 0.
     pub fn reset_request_count(&self) -> Self {
         Data {
-            config: self.config.clone(),
             request_counter: Default::default(),
+            request_limit: self.config.request_limit(),
+            ..self.nested_data()
         }
     }
     /// Total number of outgoing HTTP requests made with this data.
     pub fn request_count(&self) -> u32 {
         self.request_counter.load(Ordering::Relaxed)
     }
+
+    /// Returns a copy of this data which uses different response size limits, for example to
+    /// fetch a collection which is known to be large. The limits also apply to all nested
+    /// requests made with the returned data.
+    pub fn with_response_size_limits(&self, response_size_limits: ResponseSizeLimits) -> Self {
+        Data {
+            response_size_limits,
+            ..self.nested_data()
+        }
+    }
+
+    /// The raw activity which is currently being processed by `receive_activity`.
+    ///
+    /// This is available in [ActivityHandler::verify] and [ActivityHandler::receive], so that
+    /// the activity can be forwarded with
+    /// [SendActivityTask::prepare_forward](crate::activity_sending::SendActivityTask::prepare_forward).
+    pub fn received_activity(&self) -> Option<&ReceivedActivity> {
+        self.received_activity.as_deref()
+    }
+
+    /// Returns a copy of this data for handling the received activity.
+    pub(crate) fn with_received_activity(&self, received_activity: ReceivedActivity) -> Self {
+        Data {
+            received_activity: Some(Arc::new(received_activity)),
+            ..self.nested_data()
+        }
+    }
+
+    /// Returns a copy of this data which may make `budget.max_requests` more HTTP requests and
+    /// `budget.max_depth` more nested dereferences.
+    pub(crate) fn with_budget(&self, budget: FetchBudget) -> Self {
+        Data {
+            request_limit: self.request_count().saturating_add(budget.max_requests),
+            depth_limit: (self.dereference_chain.len() as u32).saturating_add(budget.max_depth),
+            ..self.nested_data()
+        }
+    }
+
+    /// Returns a copy of this data for verifying and parsing the object at `url`, so that
+    /// dereferences from there are counted as one level deeper. Fails with
+    /// [Error::DepthLimit] if the depth limit is already reached.
+    pub(crate) fn dereference_nested(&self, url: &Url) -> Result<Self, Error> {
+        let mut data = self.nested_data();
+        data.dereference_chain.push(url.clone());
+        if self.dereference_chain.len() as u32 >= self.depth_limit {
+            return Err(Error::DepthLimit(data.dereference_chain));
+        }
+        Ok(data)
+    }
+
+    /// Copy of this data which shares the request counter
+    fn nested_data(&self) -> Self {
+        Data {
+            config: self.config.clone(),
+            request_counter: self.request_counter.clone(),
+            request_limit: self.request_limit,
+            dereference_chain: self.dereference_chain.clone(),
+            depth_limit: self.depth_limit,
+            response_size_limits: self.response_size_limits,
+            received_activity: self.received_activity.clone(),
+        }
+    }
+}
+
+/// An activity exactly as it was received in the inbox, see [Data::received_activity].
+#[derive(Clone, Debug)]
+pub struct ReceivedActivity {
+    pub(crate) id: Url,
+    pub(crate) actor: Url,
+    pub(crate) headers: HeaderMap,
+    /// The request body
+    pub body: Bytes,
+}
+
+impl ReceivedActivity {
+    /// Id of the activity
+    pub fn id(&self) -> &Url {
+        &self.id
+    }
+
+    /// Id of the actor who created the activity
+    pub fn actor(&self) -> &Url {
+        &self.actor
+    }
+
+    /// HTTP headers of the request, including the original `Signature` and `Digest`
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
 }
 
 impl<T: Clone> Deref for Data<T> {
@@ -325,3 +665,94 @@ impl<T: Clone> FederationMiddleware<T> {
         FederationMiddleware(config)
     }
 }
+
+/// Middleware which requires HTTP signatures for fetching local objects, also known as
+/// authorized fetch or secure mode.
+/// <https://docs.joinmastodon.org/spec/activitypub/#secure-mode>
+///
+/// `GET` and `HEAD` requests without valid signature are rejected with `401 Unauthorized`. If the
+/// signing actor's domain is rejected by the [UrlVerifier], the response is `403 Forbidden`.
+/// Otherwise the signing actor of type `A` is inserted into the request extensions, where
+/// handlers can read it. Other request methods are passed through unchanged.
+///
+/// Routes such as webfinger, nodeinfo and the instance actor need to be reachable without
+/// signature, so that remote instances can fetch the key for signing. Allow them with
+/// [AuthorizedFetchMiddleware::allow_unsigned_path].
+///
+/// The middleware reads the config provided by [FederationMiddleware], so that needs to be
+/// registered as outer middleware.
+pub struct AuthorizedFetchMiddleware<A> {
+    pub(crate) unsigned_paths: Arc<Vec<String>>,
+    _actor: PhantomData<fn() -> A>,
+}
+
+impl<A> AuthorizedFetchMiddleware<A>
+where
+    A: Object + Actor,
+    <A as Object>::Error: From<Error> + From<anyhow::Error>,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2>,
+{
+    /// Construct a new middleware instance which requires signatures for all paths
+    pub fn new() -> Self {
+        AuthorizedFetchMiddleware {
+            unsigned_paths: Default::default(),
+            _actor: PhantomData,
+        }
+    }
+
+    /// Allow requests to the given path without signature. Paths ending with `*` match all paths
+    /// which start with the given prefix, for example `/nodeinfo/*`.
+    pub fn allow_unsigned_path(mut self, path: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.unsigned_paths).push(path.into());
+        self
+    }
+
+    fn is_unsigned_path(&self, path: &str) -> bool {
+        self.unsigned_paths
+            .iter()
+            .any(|p| match p.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => path == p,
+            })
+    }
+
+    /// Checks the signature of a request. Returns the signing actor, or `None` if the request
+    /// doesn't need to be signed.
+    pub(crate) async fn verify<'a, H>(
+        &self,
+        headers: H,
+        method: &Method,
+        uri: &Uri,
+        data: &Data<<A as Object>::DataType>,
+    ) -> Result<Option<A>, StatusCode>
+    where
+        H: IntoIterator<Item = (&'a HeaderName, &'a HeaderValue)>,
+    {
+        if !matches!(*method, Method::GET | Method::HEAD) || self.is_unsigned_path(uri.path()) {
+            return Ok(None);
+        }
+        signing_actor_status(headers, method, uri, data)
+            .await
+            .map(Some)
+    }
+}
+
+impl<A> Clone for AuthorizedFetchMiddleware<A> {
+    fn clone(&self) -> Self {
+        AuthorizedFetchMiddleware {
+            unsigned_paths: self.unsigned_paths.clone(),
+            _actor: PhantomData,
+        }
+    }
+}
+
+impl<A> Default for AuthorizedFetchMiddleware<A>
+where
+    A: Object + Actor,
+    <A as Object>::Error: From<Error> + From<anyhow::Error>,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,26 +1,58 @@
 //! Error messages returned by this library
 
+#[cfg(doc)]
+use crate::fetch::negative_cache::NegativeCache;
+use crate::fetch::{negative_cache::FetchFailure, Tombstone};
+use anyhow::anyhow;
+use itertools::Itertools;
+use url::Url;
+
 /// Error messages returned by this library
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     /// Object was not found in local database
     #[error("Object was not found in local database")]
     NotFound,
-    /// Request limit was reached during fetch
-    #[error("Request limit was reached during fetch")]
-    RequestLimit,
-    /// Response body limit was reached during fetch
-    #[error("Response body limit was reached during fetch")]
-    ResponseBodyLimit,
-    /// Object to be fetched was deleted
-    #[error("Object to be fetched was deleted")]
-    ObjectDeleted,
+    /// Request limit was reached during fetch. Contains the urls of the objects which were being
+    /// dereferenced, outermost first.
+    #[error("Request limit was reached during fetch of {}", .0.iter().join(" -> "))]
+    RequestLimit(Vec<Url>),
+    /// Limit for nested dereferencing was reached during fetch. Contains the urls of the objects
+    /// which were being dereferenced, outermost first.
+    #[error("Depth limit was reached during fetch of {}", .0.iter().join(" -> "))]
+    DepthLimit(Vec<Url>),
+    /// Response body from the given url exceeded the limit in bytes, see
+    /// [ResponseSizeLimits](crate::fetch::ResponseSizeLimits)
+    #[error("Response body from {0} exceeded limit of {1} bytes")]
+    ResponseBodyLimit(Url, usize),
+    /// Object to be fetched was deleted, either with HTTP 410 or a `Tombstone` body
+    #[error("Object {0} was deleted")]
+    ObjectDeleted(Url, Box<Tombstone>),
+    /// Remote server responded to a conditional fetch with `304 Not Modified`
+    #[error("Object to be fetched was not modified")]
+    NotModified,
+    /// Remote server returned HTTP 404 for the object
+    #[error("Remote object {0} was not found")]
+    ObjectNotFound(Url),
+    /// Fetching the object failed recently, so it was not requested again. See [NegativeCache].
+    #[error("Fetching {0} failed recently with {1:?}")]
+    FetchFailedRecently(Url, FetchFailure),
+    /// Fetched object has a `Content-Type` which is not in
+    /// [FederationConfigBuilder::accepted_content_types](crate::config::FederationConfigBuilder::accepted_content_types)
+    #[error("Fetched object {0} has unexpected content type {1:?}")]
+    UnexpectedContentType(Url, Option<String>),
+    /// Fetched object has an id which is neither the request URL nor the final URL after redirects
+    #[error("Fetched object has wrong id {0:?}")]
+    FetchWrongId(Option<Url>),
     /// url verification error
     #[error("URL failed verification: {0}")]
     UrlVerificationError(anyhow::Error),
     /// Incoming activity has invalid digest for body
     #[error("Incoming activity has invalid digest for body")]
     ActivityBodyDigestInvalid,
+    /// Incoming activity only has body digests with unsupported hash algorithms
+    #[error("Incoming activity has digest with unsupported algorithm: {0}")]
+    ActivityBodyDigestUnsupported(String),
     /// Incoming activity has invalid signature
     #[error("Incoming activity has invalid signature")]
     ActivitySignatureInvalid,
@@ -39,6 +71,38 @@ impl Error {
     {
         Error::Other(error.into())
     }
+
+    /// Returns a copy of the error, for example to pass the result of a fetch to concurrent
+    /// fetches of the same object. Wrapped errors can't be cloned, so only their message is
+    /// copied.
+    pub(crate) fn duplicate(&self) -> Self {
+        match self {
+            Error::NotFound => Error::NotFound,
+            Error::RequestLimit(chain) => Error::RequestLimit(chain.clone()),
+            Error::DepthLimit(chain) => Error::DepthLimit(chain.clone()),
+            Error::ResponseBodyLimit(url, limit) => Error::ResponseBodyLimit(url.clone(), *limit),
+            Error::ObjectDeleted(url, tombstone) => {
+                Error::ObjectDeleted(url.clone(), tombstone.clone())
+            }
+            Error::NotModified => Error::NotModified,
+            Error::ObjectNotFound(url) => Error::ObjectNotFound(url.clone()),
+            Error::FetchFailedRecently(url, failure) => {
+                Error::FetchFailedRecently(url.clone(), *failure)
+            }
+            Error::UnexpectedContentType(url, content_type) => {
+                Error::UnexpectedContentType(url.clone(), content_type.clone())
+            }
+            Error::FetchWrongId(id) => Error::FetchWrongId(id.clone()),
+            Error::UrlVerificationError(e) => Error::UrlVerificationError(anyhow!("{e:#}")),
+            Error::ActivityBodyDigestInvalid => Error::ActivityBodyDigestInvalid,
+            Error::ActivityBodyDigestUnsupported(algorithms) => {
+                Error::ActivityBodyDigestUnsupported(algorithms.clone())
+            }
+            Error::ActivitySignatureInvalid => Error::ActivitySignatureInvalid,
+            Error::WebfingerResolveFailed => Error::WebfingerResolveFailed,
+            Error::Other(e) => Error::Other(anyhow!("{e:#}")),
+        }
+    }
 }
 
 impl PartialEq for Error {
@@ -1,4 +1,9 @@
-use crate::{config::Data, error::Error, fetch::fetch_object_http, traits::Collection};
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::{fetch_object_http_with_limit, DereferenceOptions, FetchMode},
+    traits::Collection,
+};
 use serde::{Deserialize, Serialize};
 use std::{
     fmt::{Debug, Display, Formatter},
@@ -40,7 +45,39 @@ where
     where
         <Kind as Collection>::Error: From<Error>,
     {
-        let res = fetch_object_http(&self.0, data).await?;
+        self.dereference_with_options(owner, data, DereferenceOptions::default())
+            .await
+    }
+
+    /// Same as [CollectionId::dereference], but with custom options. Collections are not cached,
+    /// so [FetchMode::CacheOnly] and [FetchMode::NeverFetch] return [Error::NotFound] without
+    /// making a request, and [DereferenceOptions::max_age] is ignored.
+    pub async fn dereference_with_options(
+        &self,
+        owner: &<Kind as Collection>::Owner,
+        data: &Data<<Kind as Collection>::DataType>,
+        options: DereferenceOptions,
+    ) -> Result<Kind, <Kind as Collection>::Error>
+    where
+        <Kind as Collection>::Error: From<Error>,
+    {
+        match options.mode {
+            FetchMode::CacheOnly | FetchMode::NeverFetch => return Err(Error::NotFound.into()),
+            FetchMode::ForceRefresh => data.config.negative_cache.invalidate(&self.0).await,
+            FetchMode::Default => {}
+        }
+        let budget_data;
+        let data = match options.budget {
+            Some(budget) => {
+                budget_data = data.with_budget(budget);
+                &budget_data
+            }
+            None => data,
+        };
+        let data = &data.dereference_nested(&self.0)?;
+        let res =
+            fetch_object_http_with_limit(&self.0, data, data.response_size_limits.collection, None)
+                .await?;
         let redirect_url = &res.url;
         Kind::verify(&res.object, redirect_url, data).await?;
         Kind::from_json(res.object, owner, data).await
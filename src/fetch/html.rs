@@ -0,0 +1,108 @@
+//! Discovery of ActivityPub representations which are linked from HTML pages
+
+/// Returns the `href` of the first `<link rel="alternate">` tag which points to an ActivityPub
+/// representation, for example
+/// `<link rel="alternate" type="application/activity+json" href="https://example.com/p/1">`.
+pub(crate) fn find_activity_link(html: &str) -> Option<String> {
+    let lowercase = html.to_ascii_lowercase();
+    let mut rest = lowercase.as_str();
+    while let Some(start) = rest.find("<link") {
+        let offset = lowercase.len() - rest.len() + start;
+        let end = html[offset..].find('>').map(|end| offset + end)?;
+        let tag = &html[offset + "<link".len()..end];
+        rest = &lowercase[end..];
+
+        let attributes = parse_attributes(tag);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        };
+        let is_alternate = attribute("rel").is_some_and(|rel| {
+            rel.split_whitespace()
+                .any(|r| r.eq_ignore_ascii_case("alternate"))
+        });
+        let is_activitypub = attribute("type").is_some_and(is_activitypub_media_type);
+        if let (true, true, Some(href)) = (is_alternate, is_activitypub, attribute("href")) {
+            return Some(href.to_string());
+        }
+    }
+    None
+}
+
+fn is_activitypub_media_type(media_type: &str) -> bool {
+    let media_type = media_type.to_ascii_lowercase();
+    let essence = media_type.split(';').next().unwrap_or_default().trim();
+    essence == "application/activity+json"
+        || (essence == "application/ld+json" && media_type.contains("activitystreams"))
+}
+
+/// Parses `name="value"`, `name='value'` and `name=value` attributes of a tag. Values have basic
+/// html entities decoded.
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = vec![];
+    let mut chars = tag.trim_end_matches('/').chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let name: String =
+            std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && *c != '=')).collect();
+        if name.is_empty() {
+            break;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next_if_eq(&'=').is_none() {
+            attributes.push((name, String::new()));
+            continue;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let value: String = match chars.next_if(|c| *c == '"' || *c == '\'') {
+            Some(quote) => {
+                let value = std::iter::from_fn(|| chars.next_if(|c| *c != quote)).collect();
+                chars.next();
+                value
+            }
+            None => std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect(),
+        };
+        attributes.push((name, decode_entities(&value)));
+    }
+    attributes
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_activity_link() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="/style.css">
+            <LINK REL="alternate" type="application/rss+xml" href="/feed">
+            <link href='https://example.com/?p=1&amp;ap=1' rel="alternate"
+                type='application/ld+json; profile="https://www.w3.org/ns/activitystreams"' />
+            </head></html>"#;
+        assert_eq!(
+            Some("https://example.com/?p=1&ap=1".to_string()),
+            find_activity_link(html)
+        );
+
+        let html = r#"<link rel="alternate" type="application/activity+json" href=/users/1>"#;
+        assert_eq!(Some("/users/1".to_string()), find_activity_link(html));
+
+        let html = r#"<link rel="alternate" type="application/ld+json" href="/schema">"#;
+        assert_eq!(None, find_activity_link(html));
+        assert_eq!(
+            None,
+            find_activity_link("<html><body>Not found</body></html>")
+        );
+    }
+}
@@ -5,30 +5,273 @@
 use crate::{
     config::Data,
     error::Error,
-    http_signatures::sign_request,
+    http_signatures::send_signed_request,
     reqwest_shim::ResponseExt,
+    FEDERATION_ACCEPT_HEADER,
     FEDERATION_CONTENT_TYPE,
 };
+use anyhow::anyhow;
 use bytes::Bytes;
-use http::StatusCode;
+use chrono::{DateTime, Utc};
+use http::{
+    header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION},
+    HeaderMap,
+    StatusCode,
+};
+use reqwest::Response;
 use serde::de::DeserializeOwned;
-use std::sync::atomic::Ordering;
-use tracing::info;
+use serde_json::Value;
+use std::{sync::atomic::Ordering, time::Duration};
+use tracing::{debug, info};
 use url::Url;
 
 /// Typed wrapper for collection IDs
 pub mod collection_id;
+mod html;
+/// Caches failed fetches of remote objects
+pub mod negative_cache;
 /// Typed wrapper for Activitypub Object ID which helps with dereferencing and caching
 pub mod object_id;
 /// Resolves identifiers of the form `name@example.com`
 pub mod webfinger;
 
+/// Limits for dereferencing an object, including all objects which are dereferenced while
+/// verifying and parsing it.
+///
+/// See [ObjectId::dereference_with_budget](object_id::ObjectId::dereference_with_budget). By
+/// default the limits from
+/// [FederationConfigBuilder::http_fetch_limit](crate::config::FederationConfigBuilder::http_fetch_limit)
+/// and
+/// [FederationConfigBuilder::dereference_depth_limit](crate::config::FederationConfigBuilder::dereference_depth_limit)
+/// are used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FetchBudget {
+    /// Maximum number of nested dereferences, exceeding it returns [Error::DepthLimit]
+    pub max_depth: u32,
+    /// Maximum number of HTTP requests, exceeding it returns [Error::RequestLimit]
+    pub max_requests: u32,
+}
+
+/// Determines when an object is fetched over HTTP instead of read from the local database, see
+/// [DereferenceOptions].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FetchMode {
+    /// Read from the database, and fetch over HTTP if the object is missing or stale
+    #[default]
+    Default,
+    /// Always fetch over HTTP, for example after an `Update` activity was received. This also
+    /// ignores the [NegativeCache](negative_cache::NegativeCache).
+    ForceRefresh,
+    /// Read from the database regardless of its age, and only fetch over HTTP if it is missing
+    CacheOnly,
+    /// Only read from the database, and return [Error::NotFound] if the object is missing
+    NeverFetch,
+}
+
+/// Options for [ObjectId::dereference_with_options](object_id::ObjectId::dereference_with_options)
+/// and [CollectionId::dereference_with_options](collection_id::CollectionId::dereference_with_options).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DereferenceOptions {
+    mode: FetchMode,
+    max_age: Option<Duration>,
+    budget: Option<FetchBudget>,
+}
+
+impl DereferenceOptions {
+    /// Always fetch the object over HTTP, see [FetchMode::ForceRefresh]
+    pub fn force_refresh(self) -> Self {
+        self.mode(FetchMode::ForceRefresh)
+    }
+
+    /// Use the object from the database regardless of its age, see [FetchMode::CacheOnly]
+    pub fn cache_only(self) -> Self {
+        self.mode(FetchMode::CacheOnly)
+    }
+
+    /// Never make HTTP requests, see [FetchMode::NeverFetch]
+    pub fn never_fetch(self) -> Self {
+        self.mode(FetchMode::NeverFetch)
+    }
+
+    /// Sets when the object is fetched over HTTP
+    pub fn mode(mut self, mode: FetchMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Refetch the object if it was refreshed longer ago than this. Overrides
+    /// [Object::refetch_interval](crate::traits::Object::refetch_interval) and the configured
+    /// default, only used with [FetchMode::Default].
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Custom limits for this object and all objects which are dereferenced while verifying and
+    /// parsing it, see [FetchBudget]
+    pub fn budget(mut self, budget: FetchBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+}
+
+/// Maximum size in bytes of response bodies, exceeding it returns [Error::ResponseBodyLimit].
+///
+/// Configured with
+/// [FederationConfigBuilder::response_size_limits](crate::config::FederationConfigBuilder::response_size_limits),
+/// and can be changed for a single call with [Data::with_response_size_limits].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponseSizeLimits {
+    /// Objects fetched with [ObjectId](object_id::ObjectId) or [fetch_object_http], 200 KiB by
+    /// default
+    pub object: usize,
+    /// Collections fetched with [CollectionId](collection_id::CollectionId), 1 MiB by default
+    pub collection: usize,
+    /// Webfinger responses, 20 KiB by default
+    pub webfinger: usize,
+    /// Bodies of error responses from remote servers, 20 KiB by default
+    pub error_body: usize,
+}
+
+impl Default for ResponseSizeLimits {
+    fn default() -> Self {
+        ResponseSizeLimits {
+            object: 200 * 1024,
+            collection: 1024 * 1024,
+            webfinger: 20 * 1024,
+            error_body: 20 * 1024,
+        }
+    }
+}
+
+/// Values of the `ETag` and `Last-Modified` headers of a fetched object, which are used to only
+/// download the object again if it was modified. See
+/// [Object::cache_validators](crate::traits::Object::cache_validators).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheValidators {
+    /// Value of the `ETag` header, sent as `If-None-Match` on refetch
+    pub etag: Option<String>,
+    /// Value of the `Last-Modified` header, sent as `If-Modified-Since` on refetch
+    pub last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|h| h.to_str().ok())
+                .map(str::to_string)
+        };
+        CacheValidators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    /// Returns true if neither header is set
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Information about a deleted remote object, taken from its `Tombstone` representation if the
+/// remote server returned one. See [Error::ObjectDeleted].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tombstone {
+    /// The `formerType` of the object, for example `Note`
+    pub former_type: Option<String>,
+    /// When the object was deleted
+    pub deleted: Option<DateTime<Utc>>,
+}
+
+impl Tombstone {
+    /// Returns the tombstone information if the json has type `Tombstone`.
+    fn from_json(json: &Value) -> Option<Self> {
+        let is_tombstone = match json.get("type") {
+            Some(Value::String(kind)) => kind == "Tombstone",
+            Some(Value::Array(kinds)) => kinds.iter().any(|k| k == "Tombstone"),
+            _ => false,
+        };
+        if !is_tombstone {
+            return None;
+        }
+        let former_type = match json.get("formerType") {
+            Some(Value::String(kind)) => Some(kind.clone()),
+            Some(Value::Array(kinds)) => kinds.first().and_then(Value::as_str).map(str::to_string),
+            _ => None,
+        };
+        let deleted = json
+            .get("deleted")
+            .and_then(Value::as_str)
+            .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+            .map(|d| d.with_timezone(&Utc));
+        Some(Tombstone {
+            former_type,
+            deleted,
+        })
+    }
+}
+
 /// Response from fetching a remote object
 pub struct FetchObjectResponse<Kind> {
     /// The resolved object
     pub object: Kind,
     /// Contains the final URL (different from request URL in case of redirect)
     pub url: Url,
+    /// All URLs which were requested, starting with the request URL and ending with the final
+    /// URL. Contains only a single item if there was no redirect.
+    pub redirect_chain: Vec<Url>,
+    /// Validator headers of the response, which can be used for a conditional refetch
+    pub cache_validators: CacheValidators,
+}
+
+/// Determines which redirects are followed when fetching remote data, see
+/// [FederationConfigBuilder::redirect_policy](crate::config::FederationConfigBuilder::redirect_policy).
+///
+/// Regardless of the policy, every URL in the redirect chain is checked with the
+/// [UrlVerifier](crate::config::UrlVerifier), and the fetched object needs to have either the
+/// request URL or the final URL as id.
+///
+/// Redirects are only handled by this library if the HTTP client doesn't follow them itself. The
+/// default client is configured accordingly, custom clients should be created with
+/// `.redirect(reqwest::redirect::Policy::none())`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Don't follow any redirects
+    None,
+    /// Follow up to the given number of redirects, but only within the origin of the request URL
+    SameOrigin(usize),
+    /// Follow up to the given number of redirects to any origin
+    Limited(usize),
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        RedirectPolicy::Limited(10)
+    }
+}
+
+impl RedirectPolicy {
+    /// Checks if a redirect to `next` is allowed, after the URLs in `chain` were requested.
+    fn check(&self, chain: &[Url], next: &Url) -> Result<(), Error> {
+        let redirects = chain.len();
+        let allowed = match self {
+            RedirectPolicy::None => false,
+            RedirectPolicy::SameOrigin(max_hops) => {
+                redirects <= *max_hops && chain.first().map(Url::origin) == Some(next.origin())
+            }
+            RedirectPolicy::Limited(max_hops) => redirects <= *max_hops,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(Error::UrlVerificationError(anyhow!(
+                "Redirect from {} to {next} is not allowed by {self:?}",
+                chain.last().map(Url::as_str).unwrap_or_default()
+            )))
+        }
+    }
 }
 
 /// Fetch a remote object over HTTP and convert to `Kind`.
@@ -38,24 +281,158 @@ pub struct FetchObjectResponse<Kind> {
 /// behaviour is undesired.
 ///
 /// Every time an object is fetched via HTTP, [RequestData.request_counter] is incremented by one.
-/// If the value exceeds [FederationSettings.http_fetch_limit], the request is aborted with
-/// [Error::RequestLimit]. This prevents denial of service attacks where an attack triggers
-/// infinite, recursive fetching of data.
+/// If the value exceeds [FederationSettings.http_fetch_limit] or the [FetchBudget], the request
+/// is aborted with [Error::RequestLimit]. This prevents denial of service attacks where an attack
+/// triggers infinite, recursive fetching of data.
+///
+/// Redirects are followed according to the configured [RedirectPolicy]. The fetched object must
+/// have either `url` or the final URL after redirects as its id. Otherwise the object is fetched
+/// once more from the id which it claims, and if that doesn't match either
+/// [Error::FetchWrongId] is returned. Otherwise [FetchObjectResponse::url] is the canonical id,
+/// and [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference) reads the object
+/// under that id on later calls, without fetching the url again. This check can be disabled with
+/// [FederationConfigBuilder::check_fetched_id](crate::config::FederationConfigBuilder::check_fetched_id).
+///
+/// The response body may be at most [ResponseSizeLimits::object] bytes.
+///
+/// If the server returns an HTML page, for example because the url was copied from a browser,
+/// the `<link rel="alternate">` to its ActivityPub representation is followed once, like a
+/// redirect. Pages without such a link fail with [Error::UnexpectedContentType].
+///
+/// If the object was deleted, [Error::ObjectDeleted] is returned. This is the case for HTTP 410,
+/// and for responses with a `Tombstone` body regardless of the status code.
+///
+/// Other error statuses return [Error::Other] with the status, and at most
+/// [ResponseSizeLimits::error_body] bytes of the response body.
 ///
-/// The `Accept` header will be set to the content of [`FEDERATION_CONTENT_TYPE`].
+/// Failed fetches are remembered in the [NegativeCache](negative_cache::NegativeCache), and
+/// return [Error::FetchFailedRecently] without a request until the entry expires.
+///
+/// The `Accept` header will be set to the content of [`FEDERATION_ACCEPT_HEADER`]. Responses
+/// with a `Content-Type` which is not in
+/// [FederationConfigBuilder::accepted_content_types](crate::config::FederationConfigBuilder::accepted_content_types)
+/// fail with [Error::UnexpectedContentType].
 pub async fn fetch_object_http<T: Clone, Kind: DeserializeOwned>(
     url: &Url,
     data: &Data<T>,
 ) -> Result<FetchObjectResponse<Kind>, Error> {
-    fetch_object_http_with_accept(url, data, FEDERATION_CONTENT_TYPE).await
+    fetch_object_http_with_limit(url, data, data.response_size_limits.object, None).await
+}
+
+/// Same as [`fetch_object_http`], but with a custom limit for the response body size.
+///
+/// If `validators` are given, the request is conditional and returns [Error::NotModified] if
+/// the remote server responds with `304 Not Modified`.
+pub(crate) async fn fetch_object_http_with_limit<T: Clone, Kind: DeserializeOwned>(
+    url: &Url,
+    data: &Data<T>,
+    size_limit: usize,
+    validators: Option<&CacheValidators>,
+) -> Result<FetchObjectResponse<Kind>, Error> {
+    let negative_cache = &data.config.negative_cache;
+    negative_cache.check(url)?;
+    let res = fetch_object_json(url, data, size_limit, validators).await;
+    if let Err(e) = &res {
+        negative_cache.insert_error(url, e).await;
+    }
+    let res = res?;
+    // not cached on failure, because the same url may be fetched as another type
+    Ok(FetchObjectResponse {
+        object: serde_json::from_value(res.object).map_err(Error::other)?,
+        url: res.url,
+        redirect_chain: res.redirect_chain,
+        cache_validators: res.cache_validators,
+    })
+}
+
+/// Fetches the object as json and checks its id.
+async fn fetch_object_json<T: Clone>(
+    url: &Url,
+    data: &Data<T>,
+    size_limit: usize,
+    validators: Option<&CacheValidators>,
+) -> Result<FetchObjectResponse<Value>, Error> {
+    let mut res: FetchObjectResponse<Value> =
+        fetch_object_http_with_kind(url, data, FetchKind::ActivityPub, size_limit, validators)
+            .await?;
+    if data.config.check_fetched_id {
+        match fetched_id(url, &res.url, &res.object) {
+            Ok(()) => {}
+            // the body can't be trusted for another id, so refetch it from its canonical location
+            Err(Some(canonical_id)) => {
+                debug!("Fetched {url} has id {canonical_id}, refetching from canonical id");
+                let redirect_chain = res.redirect_chain;
+                res = fetch_object_http_with_kind(
+                    &canonical_id,
+                    data,
+                    FetchKind::ActivityPub,
+                    size_limit,
+                    None,
+                )
+                .await?;
+                if let Err(id) = fetched_id(&canonical_id, &res.url, &res.object) {
+                    return Err(Error::FetchWrongId(id));
+                }
+                res.redirect_chain = [redirect_chain, res.redirect_chain].concat();
+                data.config
+                    .canonical_ids
+                    .insert(url.clone(), canonical_id)
+                    .await;
+            }
+            Err(None) => return Err(Error::FetchWrongId(None)),
+        }
+    }
+    // some servers return deleted objects as tombstone with status 200. this is only checked
+    // after the id, so that other servers can't delete objects by returning a tombstone for them
+    if let Some(tombstone) = Tombstone::from_json(&res.object) {
+        return Err(Error::ObjectDeleted(url.clone(), Box::new(tombstone)));
+    }
+    Ok(res)
+}
+
+/// Checks that the id of the fetched object is either the request URL or the final URL. If not,
+/// returns the id which it has instead.
+fn fetched_id(url: &Url, final_url: &Url, object: &Value) -> Result<(), Option<Url>> {
+    let id = object
+        .get("id")
+        .and_then(Value::as_str)
+        .and_then(|id| Url::parse(id).ok());
+    match id {
+        Some(id) if &id == url || &id == final_url => Ok(()),
+        id => Err(id),
+    }
+}
+
+/// Kind of document which is fetched, determines the `Accept` header and how the response is
+/// handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FetchKind {
+    /// Html pages are followed to their ActivityPub alternate link, and the `Content-Type` of
+    /// the response is validated
+    ActivityPub,
+    /// Webfinger document, the response is parsed as it is
+    Webfinger,
+}
+
+impl FetchKind {
+    fn accept(self) -> &'static str {
+        match self {
+            FetchKind::ActivityPub => FEDERATION_ACCEPT_HEADER,
+            FetchKind::Webfinger => "application/jrd+json",
+        }
+    }
 }
 
 /// Fetch a remote object over HTTP and convert to `Kind`. This function works exactly as
-/// [`fetch_object_http`] except that the `Accept` header is specified in `content_type`.
-async fn fetch_object_http_with_accept<T: Clone, Kind: DeserializeOwned>(
+/// [`fetch_object_http`] except that the `Accept` header and response handling depend on
+/// `fetch_kind`, the response body may be at most `size_limit` bytes, and the id of the fetched
+/// object is not checked. The request is conditional if `validators` are given.
+async fn fetch_object_http_with_kind<T: Clone, Kind: DeserializeOwned>(
     url: &Url,
     data: &Data<T>,
-    content_type: &str,
+    fetch_kind: FetchKind,
+    size_limit: usize,
+    validators: Option<&CacheValidators>,
 ) -> Result<FetchObjectResponse<Kind>, Error> {
     let config = &data.config;
     // dont fetch local objects this way
@@ -63,38 +440,740 @@ async fn fetch_object_http_with_accept<T: Clone, Kind: DeserializeOwned>(
     config.verify_url_valid(url).await?;
     info!("Fetching remote object {}", url.to_string());
 
-    let counter = data.request_counter.fetch_add(1, Ordering::SeqCst);
-    if counter > config.http_fetch_limit {
-        return Err(Error::RequestLimit);
+    let mut redirect_chain = vec![url.clone()];
+    let mut res =
+        send_following_redirects(data, fetch_kind, validators, &mut redirect_chain).await?;
+
+    // pages which are opened in a browser often return html despite the accept header
+    let is_activitypub = fetch_kind == FetchKind::ActivityPub;
+    if is_activitypub && is_html(&res) {
+        let page_url = res.url().clone();
+        let page_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+        let html = res.bytes_limited(size_limit).await?;
+        let alternate = html::find_activity_link(&String::from_utf8_lossy(&html))
+            .ok_or(Error::UnexpectedContentType(page_url.clone(), page_type))?;
+        let alternate = page_url.join(&alternate).map_err(Error::other)?;
+        // the link is followed like a redirect
+        config.redirect_policy.check(&redirect_chain, &alternate)?;
+        config.verify_url_valid(&alternate).await?;
+        debug!("Following alternate link from {page_url} to {alternate}");
+        redirect_chain.push(alternate);
+        res = send_following_redirects(data, fetch_kind, validators, &mut redirect_chain).await?;
+    }
+
+    if res.status() == StatusCode::NOT_MODIFIED && validators.is_some() {
+        return Err(Error::NotModified);
+    }
+    let status = res.status();
+    if status == StatusCode::GONE || status == StatusCode::NOT_FOUND {
+        let final_url = res.url().clone();
+        // the body may be a tombstone, which is also returned with 404 by some servers
+        let body: Option<Value> = res
+            .json_limited(data.response_size_limits.error_body)
+            .await
+            .ok()
+            .filter(|body| !config.check_fetched_id || fetched_id(url, &final_url, body).is_ok());
+        return match body.as_ref().and_then(Tombstone::from_json) {
+            Some(tombstone) => Err(Error::ObjectDeleted(url.clone(), Box::new(tombstone))),
+            // without a tombstone the deletion can only be attributed to the same origin
+            None if status == StatusCode::GONE && final_url.origin() == url.origin() => {
+                Err(Error::ObjectDeleted(url.clone(), Default::default()))
+            }
+            None => Err(Error::ObjectNotFound(final_url)),
+        };
+    }
+    if !status.is_success() {
+        let final_url = res.url().clone();
+        let status_error = res.error_for_status_ref().err();
+        // error pages are not parsed, the body is only kept for debugging
+        let body = res
+            .text_limited(data.response_size_limits.error_body)
+            .await
+            .unwrap_or_default();
+        let context = format!("Fetching {final_url} failed with status {status}: {body}");
+        return Err(Error::Other(match status_error {
+            Some(e) => anyhow::Error::from(e).context(context),
+            None => anyhow!(context),
+        }));
+    }
+
+    let url = res.url().clone();
+    if is_activitypub {
+        let response_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok());
+        if !response_type
+            .is_some_and(|t| is_accepted_content_type(t, &config.accepted_content_types))
+        {
+            return Err(Error::UnexpectedContentType(
+                url,
+                response_type.map(str::to_string),
+            ));
+        }
+    }
+    let cache_validators = CacheValidators::from_headers(res.headers());
+    Ok(FetchObjectResponse {
+        object: res.json_limited(size_limit).await?,
+        url,
+        redirect_chain,
+        cache_validators,
+    })
+}
+
+/// Requests the last url in `redirect_chain`, and follows redirects according to the
+/// [RedirectPolicy]. All requested urls are added to `redirect_chain`, and each request counts
+/// against the request limit.
+async fn send_following_redirects<T: Clone>(
+    data: &Data<T>,
+    fetch_kind: FetchKind,
+    validators: Option<&CacheValidators>,
+    redirect_chain: &mut Vec<Url>,
+) -> Result<Response, Error> {
+    let config = &data.config;
+    let res = loop {
+        let url = redirect_chain.last().expect("chain is not empty");
+        let counter = data.request_counter.fetch_add(1, Ordering::SeqCst);
+        if counter >= data.request_limit {
+            let mut chain = data.dereference_chain.clone();
+            if chain.last() != Some(url) {
+                chain.push(url.clone());
+            }
+            return Err(Error::RequestLimit(chain));
+        }
+
+        let res = send_fetch_request(url, data, fetch_kind, validators).await?;
+        let location = res
+            .headers()
+            .get(LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .filter(|_| res.status().is_redirection());
+        let Some(location) = location else {
+            break res;
+        };
+        let next = url.join(location).map_err(Error::other)?;
+        config.redirect_policy.check(redirect_chain, &next)?;
+        config.verify_url_valid(&next).await?;
+        debug!("Following redirect from {url} to {next}");
+        redirect_chain.push(next);
+    };
+    // the client may have followed redirects on its own
+    if Some(res.url()) != redirect_chain.last() {
+        redirect_chain.push(res.url().clone());
     }
+    Ok(res)
+}
+
+pub(crate) fn default_accepted_content_types() -> Vec<String> {
+    [
+        FEDERATION_CONTENT_TYPE,
+        "application/ld+json",
+        "application/json",
+    ]
+    .map(String::from)
+    .to_vec()
+}
 
-    let req = config
-        .client
-        .get(url.as_str())
-        .header("Accept", content_type)
-        .timeout(config.request_timeout);
+/// Compares the media type without parameters, case-insensitively.
+fn is_accepted_content_type(content_type: &str, accepted: &[String]) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    accepted.iter().any(|a| a.eq_ignore_ascii_case(essence))
+}
+
+fn is_html(res: &Response) -> bool {
+    res.status().is_success()
+        && res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|h| h.trim_start().to_ascii_lowercase().starts_with("text/html"))
+}
+
+async fn send_fetch_request<T: Clone>(
+    url: &Url,
+    data: &Data<T>,
+    fetch_kind: FetchKind,
+    validators: Option<&CacheValidators>,
+) -> Result<Response, Error> {
+    let config = &data.config;
+    let req = || {
+        let mut req = config
+            .client
+            .get(url.as_str())
+            .header("Accept", fetch_kind.accept())
+            .timeout(config.request_timeout);
+        if let Some(etag) = validators.and_then(|v| v.etag.as_deref()) {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = validators.and_then(|v| v.last_modified.as_deref()) {
+            req = req.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        req
+    };
 
-    let res = if let Some((actor_id, private_key_pem)) = config.signed_fetch_actor.as_deref() {
-        let req = sign_request(
+    if let Some((actor_id, signer)) = config.signed_fetch_actor.as_deref() {
+        Ok(send_signed_request(
             req,
+            url,
             actor_id,
             Bytes::new(),
-            private_key_pem.clone(),
-            data.config.http_signature_compat,
+            &**signer,
+            config.http_signature_compat,
+            data,
         )
-        .await?;
-        config.client.execute(req).await.map_err(Error::other)?
+        .await?)
     } else {
-        req.send().await.map_err(Error::other)?
+        req().send().await.map_err(Error::other)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        fetch::negative_cache::{FetchFailure, NegativeCache, NegativeCacheTtls},
     };
+    use axum::{
+        response::{IntoResponse, Redirect},
+        routing::get,
+        Json,
+        Router,
+    };
+    use serde_json::json;
 
-    if res.status() == StatusCode::GONE {
-        return Err(Error::ObjectDeleted);
+    /// Networks of the local test servers, which need to pass the ssrf guard
+    pub(crate) fn local_networks() -> Vec<ipnet::IpNet> {
+        vec!["127.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()]
     }
 
-    let url = res.url().clone();
-    Ok(FetchObjectResponse {
-        object: res.json_limited().await?,
-        url,
-    })
+    /// Serves the router on a free local port, and returns the base url of the server. The
+    /// router is created with the base url, so that handlers can return absolute ids.
+    pub(crate) fn spawn_test_server(app: impl FnOnce(&Url) -> Router) -> Url {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let base = Url::parse(&format!("http://localhost:{port}")).unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app(&base).into_make_service());
+        tokio::spawn(server);
+        base
+    }
+
+    fn test_server() -> Url {
+        spawn_test_server(|base| {
+            let object = |id: Url| move || async move { Json(json!({ "id": id })) };
+            let cross_origin = format!("http://127.0.0.1:{}/object", base.port().unwrap());
+            Router::new()
+                .route("/object", get(object(base.join("/object").unwrap())))
+                .route("/wrong_id", get(object(base.join("/other").unwrap())))
+                .route("/alias", get(object(base.join("/object").unwrap())))
+                .route("/redirect", get(|| async { Redirect::to("/object") }))
+                .route(
+                    "/redirect_loop",
+                    get(|| async { Redirect::to("/redirect_loop").into_response() }),
+                )
+                .route(
+                    "/redirect_cross_origin",
+                    get(move || async move { Redirect::to(&cross_origin) }),
+                )
+        })
+    }
+
+    async fn fetch(
+        base: &Url,
+        path: &str,
+        redirect_policy: RedirectPolicy,
+    ) -> Result<Vec<Url>, Error> {
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .debug(true)
+            .redirect_policy(redirect_policy)
+            .ssrf_allowlist(local_networks())
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
+        let url = base.join(path).unwrap();
+        let res = fetch_object_http::<_, Value>(&url, &data).await?;
+        assert_eq!(Some(&res.url), res.redirect_chain.last());
+        Ok(res.redirect_chain)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_redirects() -> Result<(), Error> {
+        let base = test_server();
+        let local = |path: &str| format!("http://localhost:{}{path}", base.port().unwrap());
+        let ip = |path: &str| format!("http://127.0.0.1:{}{path}", base.port().unwrap());
+        let as_str = |chain: Vec<Url>| chain.into_iter().map(String::from).collect::<Vec<_>>();
+        let policy = RedirectPolicy::default();
+
+        let chain = fetch(&base, "/object", policy).await?;
+        assert_eq!(as_str(chain), [local("/object")]);
+
+        let chain = fetch(&base, "/redirect", policy).await?;
+        assert_eq!(as_str(chain), [local("/redirect"), local("/object")]);
+
+        let res = fetch(&base, "/redirect", RedirectPolicy::None).await;
+        assert!(matches!(res, Err(Error::UrlVerificationError(_))));
+
+        let res = fetch(&base, "/redirect_loop", policy).await;
+        assert!(matches!(res, Err(Error::UrlVerificationError(_))));
+
+        // the object id is on another origin than the final url, so it is refetched from there
+        let chain = fetch(&base, "/redirect_cross_origin", policy).await?;
+        let expected = [
+            local("/redirect_cross_origin"),
+            ip("/object"),
+            local("/object"),
+        ];
+        assert_eq!(as_str(chain), expected);
+        let res = fetch(
+            &base,
+            "/redirect_cross_origin",
+            RedirectPolicy::SameOrigin(10),
+        )
+        .await;
+        assert!(matches!(res, Err(Error::UrlVerificationError(_))));
+
+        // refetched from the canonical id, which returns 404
+        let res = fetch(&base, "/wrong_id", policy).await;
+        match res {
+            Err(Error::ObjectNotFound(url)) => assert_eq!(local("/other"), url.as_str()),
+            res => panic!("expected canonical id not found, got {res:?}"),
+        }
+
+        let chain = fetch(&base, "/alias", policy).await?;
+        assert_eq!(as_str(chain), [local("/alias"), local("/object")]);
+
+        let res = fetch(&base, "/missing", policy).await;
+        assert!(matches!(res, Err(Error::ObjectNotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_request_limit() -> Result<(), Error> {
+        let base = spawn_test_server(|base| {
+            let object = Json(json!({ "id": base.join("/object").unwrap() }));
+            Router::new().route("/object", get(move || async move { object }))
+        });
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .debug(true)
+            .ssrf_allowlist(local_networks())
+            .http_fetch_limit(1)
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
+        let url = base.join("/object").unwrap();
+
+        // the request is only aborted once the counter exceeds the limit
+        fetch_object_http::<_, Value>(&url, &data).await?;
+        fetch_object_http::<_, Value>(&url, &data).await?;
+        let res = fetch_object_http::<_, Value>(&url, &data).await;
+        assert!(matches!(res, Err(Error::RequestLimit(_))));
+        assert_eq!(3, data.request_count());
+
+        // budgets allow exactly the given number of requests
+        let data = data.reset_request_count().with_budget(FetchBudget {
+            max_depth: 1,
+            max_requests: 1,
+        });
+        fetch_object_http::<_, Value>(&url, &data).await?;
+        let res = fetch_object_http::<_, Value>(&url, &data).await;
+        assert!(matches!(res, Err(Error::RequestLimit(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_negative_cache() -> Result<(), Error> {
+        // returns 404 for every path
+        let base = spawn_test_server(|_| Router::new());
+        // objects which are not found are only cached if enabled
+        let ttls = NegativeCacheTtls {
+            not_found: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .debug(true)
+            .ssrf_allowlist(local_networks())
+            .negative_cache(NegativeCache::new(100, ttls))
+            .build()
+            .await
+            .unwrap();
+        let data = config.to_request_data();
+        let url = base.join("/missing").unwrap();
+
+        let res = fetch_object_http::<_, Value>(&url, &data).await;
+        assert!(matches!(res, Err(Error::ObjectNotFound(_))));
+        assert_eq!(1, data.request_count());
+
+        // the failure is cached, so no further request is made
+        let res = fetch_object_http::<_, Value>(&url, &data).await;
+        assert!(matches!(
+            res,
+            Err(Error::FetchFailedRecently(_, FetchFailure::NotFound))
+        ));
+        assert_eq!(1, data.request_count());
+
+        config.negative_cache().invalidate(&url).await;
+        let res = fetch_object_http::<_, Value>(&url, &data).await;
+        assert!(matches!(res, Err(Error::ObjectNotFound(_))));
+        assert_eq!(2, data.request_count());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_conditional() -> Result<(), Error> {
+        let base = spawn_test_server(|base| {
+            let id = base.join("/object").unwrap();
+            let object = |headers: HeaderMap| async move {
+                if headers.get(IF_NONE_MATCH).and_then(|h| h.to_str().ok()) == Some("\"v1\"") {
+                    return StatusCode::NOT_MODIFIED.into_response();
+                }
+                ([(ETAG, "\"v1\"")], Json(json!({ "id": id }))).into_response()
+            };
+            Router::new().route("/object", get(object))
+        });
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .debug(true)
+            .ssrf_allowlist(local_networks())
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
+        let url = base.join("/object").unwrap();
+        let limit = data.response_size_limits.object;
+
+        let res = fetch_object_http::<_, Value>(&url, &data).await?;
+        assert_eq!(Some("\"v1\""), res.cache_validators.etag.as_deref());
+        assert_eq!(None, res.cache_validators.last_modified);
+
+        let res = fetch_object_http_with_limit::<_, Value>(
+            &url,
+            &data,
+            limit,
+            Some(&res.cache_validators),
+        )
+        .await;
+        assert!(matches!(res, Err(Error::NotModified)));
+
+        let changed = CacheValidators {
+            etag: Some("\"v0\"".to_string()),
+            last_modified: None,
+        };
+        let res =
+            fetch_object_http_with_limit::<_, Value>(&url, &data, limit, Some(&changed)).await;
+        assert!(res.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_tombstone() -> Result<(), Error> {
+        let base = spawn_test_server(|base| {
+            let tombstone = |id: Url| {
+                json!({
+                    "id": id,
+                    "type": "Tombstone",
+                    "formerType": "Note",
+                    "deleted": "2024-01-02T03:04:05Z"
+                })
+            };
+            let ok = Json(tombstone(base.join("/ok").unwrap()));
+            let not_found = Json(tombstone(base.join("/not_found").unwrap()));
+            let moved = Json(json!({ "id": base.join("/ok").unwrap() }));
+            let mut foreign_id = base.join("/object").unwrap();
+            foreign_id.set_host(Some("127.0.0.1")).unwrap();
+            let foreign = Json(tombstone(foreign_id));
+            let foreign_not_found = foreign.clone();
+            Router::new()
+                .route("/ok", get(move || async move { ok }))
+                .route("/moved", get(move || async move { moved }))
+                .route("/foreign", get(move || async move { foreign }))
+                .route(
+                    "/foreign_not_found",
+                    get(move || async move { (StatusCode::NOT_FOUND, foreign_not_found) }),
+                )
+                .route(
+                    "/not_found",
+                    get(move || async move { (StatusCode::NOT_FOUND, not_found) }),
+                )
+                .route("/gone", get(|| async { StatusCode::GONE }))
+        });
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .debug(true)
+            .ssrf_allowlist(local_networks())
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
+
+        let expected = Tombstone {
+            former_type: Some("Note".to_string()),
+            deleted: Some("2024-01-02T03:04:05Z".parse().unwrap()),
+        };
+        for path in ["/ok", "/not_found"] {
+            let url = base.join(path).unwrap();
+            match fetch_object_http::<_, Value>(&url, &data).await {
+                Err(Error::ObjectDeleted(deleted_url, tombstone)) => {
+                    assert_eq!(deleted_url, url);
+                    assert_eq!(*tombstone, expected);
+                }
+                res => panic!("expected deleted object, got {:?}", res.err()),
+            }
+        }
+
+        let url = base.join("/gone").unwrap();
+        match fetch_object_http::<_, Value>(&url, &data).await {
+            Err(Error::ObjectDeleted(_, tombstone)) => assert_eq!(*tombstone, Tombstone::default()),
+            res => panic!("expected deleted object, got {:?}", res.err()),
+        }
+
+        // the canonical id is refetched, and is a tombstone
+        let url = base.join("/moved").unwrap();
+        match fetch_object_http::<_, Value>(&url, &data).await {
+            Err(Error::ObjectDeleted(_, tombstone)) => assert_eq!(*tombstone, expected),
+            res => panic!("expected deleted object, got {:?}", res.err()),
+        }
+
+        // tombstones with the id of another object are ignored
+        let url = base.join("/foreign").unwrap();
+        match fetch_object_http::<_, Value>(&url, &data).await {
+            Err(Error::ObjectNotFound(not_found)) => {
+                assert_eq!(Some("127.0.0.1"), not_found.host_str())
+            }
+            res => panic!("expected canonical id not found, got {:?}", res.err()),
+        }
+        let url = base.join("/foreign_not_found").unwrap();
+        match fetch_object_http::<_, Value>(&url, &data).await {
+            Err(Error::ObjectNotFound(not_found)) => assert_eq!(not_found, url),
+            res => panic!("expected object not found, got {:?}", res.err()),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_html_alternate_link() -> Result<(), Error> {
+        let html = |body: String| {
+            move || async move { ([(CONTENT_TYPE, "text/html; charset=utf-8")], body) }
+        };
+        let link = |href: &str| {
+            format!(
+                r#"<html><head><link rel="alternate" type="application/activity+json" href="{href}"></head></html>"#
+            )
+        };
+        let base = spawn_test_server(|base| {
+            let object = Json(json!({ "id": base.join("/object").unwrap() }));
+            let cross_origin = format!("http://127.0.0.1:{}/object", base.port().unwrap());
+            Router::new()
+                .route("/page", get(html(link("/object"))))
+                .route("/cross_origin_page", get(html(link(&cross_origin))))
+                .route(
+                    "/plain",
+                    get(html("<html><body>Hello</body></html>".into())),
+                )
+                .route("/object", get(move || async move { object }))
+        });
+        let data = |redirect_policy| async move {
+            FederationConfig::builder()
+                .domain("example.com")
+                .app_data(())
+                .debug(true)
+                .redirect_policy(redirect_policy)
+                .ssrf_allowlist(local_networks())
+                .build()
+                .await
+                .unwrap()
+                .to_request_data()
+        };
+        let default_data = data(RedirectPolicy::default()).await;
+
+        let url = base.join("/page").unwrap();
+        let res = fetch_object_http::<_, Value>(&url, &default_data).await?;
+        assert_eq!(base.join("/object").unwrap(), res.url);
+        assert_eq!(2, res.redirect_chain.len());
+        assert_eq!(2, default_data.request_count());
+
+        let url = base.join("/plain").unwrap();
+        let res = fetch_object_http::<_, Value>(&url, &default_data).await;
+        match res {
+            Err(Error::UnexpectedContentType(page_url, Some(content_type))) => {
+                assert_eq!(url, page_url);
+                assert_eq!("text/html; charset=utf-8", content_type);
+            }
+            res => panic!("expected unexpected content type, got {:?}", res.err()),
+        }
+
+        // the link is checked against the redirect policy
+        let url = base.join("/cross_origin_page").unwrap();
+        let same_origin = data(RedirectPolicy::SameOrigin(10)).await;
+        let res = fetch_object_http::<_, Value>(&url, &same_origin).await;
+        assert!(matches!(res, Err(Error::UrlVerificationError(_))));
+        let no_redirects = data(RedirectPolicy::None).await;
+        let res = fetch_object_http::<_, Value>(&url, &no_redirects).await;
+        assert!(matches!(res, Err(Error::UrlVerificationError(_))));
+        assert_eq!(1, no_redirects.request_count());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_content_type() -> Result<(), Error> {
+        let base = spawn_test_server(|base| {
+            let object_id = base.join("/object").unwrap();
+            let activity = json!({ "id": base.join("/activity").unwrap() }).to_string();
+            Router::new()
+                .route(
+                    "/object",
+                    get(|headers: HeaderMap| async move {
+                        let accept = headers.get("accept").unwrap().to_str().unwrap().to_string();
+                        Json(json!({ "id": object_id, "accept": accept }))
+                    }),
+                )
+                .route(
+                    "/activity",
+                    get(|| async { ([(CONTENT_TYPE, "application/activity+json")], activity) }),
+                )
+                .route("/text", get(|| async { "Internal server error" }))
+        });
+        let config = |accepted: Option<Vec<String>>| async move {
+            let mut builder = FederationConfig::builder();
+            builder
+                .domain("example.com")
+                .app_data(())
+                .debug(true)
+                .ssrf_allowlist(local_networks());
+            if let Some(accepted) = accepted {
+                builder.accepted_content_types(accepted);
+            }
+            builder.build().await.unwrap().to_request_data()
+        };
+        let data = config(None).await;
+
+        let url = base.join("/object").unwrap();
+        let res = fetch_object_http::<_, Value>(&url, &data).await?;
+        assert_eq!(FEDERATION_ACCEPT_HEADER, res.object["accept"]);
+
+        let url = base.join("/text").unwrap();
+        let res = fetch_object_http::<_, Value>(&url, &data).await;
+        match res {
+            Err(Error::UnexpectedContentType(_, Some(content_type))) => {
+                assert!(content_type.starts_with("text/plain"))
+            }
+            res => panic!("expected unexpected content type, got {:?}", res.err()),
+        }
+
+        let data = config(Some(vec![FEDERATION_CONTENT_TYPE.to_string()])).await;
+        let url = base.join("/activity").unwrap();
+        fetch_object_http::<_, Value>(&url, &data).await?;
+        let url = base.join("/object").unwrap();
+        let res = fetch_object_http::<_, Value>(&url, &data).await;
+        match res {
+            Err(Error::UnexpectedContentType(res_url, Some(content_type))) => {
+                assert_eq!(url, res_url);
+                assert_eq!("application/json", content_type);
+            }
+            res => panic!("expected unexpected content type, got {:?}", res.err()),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_error_status() -> Result<(), Error> {
+        let base = spawn_test_server(|base| {
+            let object = Json(json!({ "id": base.join("/unavailable").unwrap() }));
+            let large = "a".repeat(100 * 1024);
+            Router::new()
+                .route(
+                    "/unavailable",
+                    get(move || async move { (StatusCode::SERVICE_UNAVAILABLE, object) }),
+                )
+                .route(
+                    "/error",
+                    get(move || async move { (StatusCode::INTERNAL_SERVER_ERROR, large) }),
+                )
+        });
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .debug(true)
+            .ssrf_allowlist(local_networks())
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
+
+        // the body of error responses is never parsed as object, even if it has the right id
+        let url = base.join("/unavailable").unwrap();
+        match fetch_object_http::<_, Value>(&url, &data).await {
+            Err(Error::Other(e)) => {
+                let status = e
+                    .downcast_ref::<reqwest::Error>()
+                    .and_then(reqwest::Error::status);
+                assert_eq!(Some(StatusCode::SERVICE_UNAVAILABLE), status);
+                assert!(e.to_string().contains("/unavailable"), "{e}");
+            }
+            res => panic!("expected status error, got {:?}", res.err()),
+        }
+
+        // error bodies above the error body limit still result in a status error
+        let url = base.join("/error").unwrap();
+        match fetch_object_http::<_, Value>(&url, &data).await {
+            Err(Error::Other(e)) => {
+                let status = e
+                    .downcast_ref::<reqwest::Error>()
+                    .and_then(reqwest::Error::status);
+                assert_eq!(Some(StatusCode::INTERNAL_SERVER_ERROR), status);
+            }
+            res => panic!("expected status error, got {:?}", res.err()),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_size_limits() -> Result<(), Error> {
+        let base = spawn_test_server(|base| {
+            let content = "a".repeat(300 * 1024);
+            let large = Json(json!({ "id": base.join("/large").unwrap(), "content": content }));
+            Router::new().route("/large", get(move || async move { large }))
+        });
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .debug(true)
+            .ssrf_allowlist(local_networks())
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
+        let url = base.join("/large").unwrap();
+
+        let res = fetch_object_http::<_, Value>(&url, &data).await;
+        match res {
+            Err(Error::ResponseBodyLimit(error_url, limit)) => {
+                assert_eq!(error_url, url);
+                assert_eq!(limit, ResponseSizeLimits::default().object);
+            }
+            _ => panic!("expected response body limit, got {:?}", res.err()),
+        }
+
+        let data = data.with_response_size_limits(ResponseSizeLimits {
+            object: 1024 * 1024,
+            ..Default::default()
+        });
+        fetch_object_http::<_, Value>(&url, &data).await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,315 @@
+use crate::{error::Error, fetch::Tombstone};
+use moka::{future::Cache, Expiry};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Reason why fetching a remote object failed, determines how long the failure is cached in the
+/// [NegativeCache].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchFailure {
+    /// Remote server returned HTTP 404
+    NotFound,
+    /// Object was deleted, this is returned as [Error::ObjectDeleted]
+    Deleted,
+    /// Request timed out or connection failed
+    Timeout,
+    /// Fetched object has the wrong id, see [Error::FetchWrongId]
+    Verification,
+    /// Remote server returned another error status, or an unexpected content type
+    Other,
+}
+
+impl FetchFailure {
+    /// Returns the class of an error returned by a fetch, or `None` if the error doesn't depend
+    /// on the remote server and should not be cached.
+    ///
+    /// Wrapped errors are only cached if they are HTTP status, connection or timeout errors of
+    /// the request. Other errors may be local failures, for example of the signer, which must
+    /// not block the remote object.
+    pub(crate) fn from_error(error: &Error) -> Option<Self> {
+        match error {
+            Error::ObjectNotFound(_) => Some(FetchFailure::NotFound),
+            Error::ObjectDeleted(..) => Some(FetchFailure::Deleted),
+            Error::FetchWrongId(_) => Some(FetchFailure::Verification),
+            Error::UnexpectedContentType(..) => Some(FetchFailure::Other),
+            Error::Other(error) => {
+                let reqwest_error = error.downcast_ref::<reqwest::Error>().or_else(|| {
+                    match error.downcast_ref::<reqwest_middleware::Error>() {
+                        Some(reqwest_middleware::Error::Reqwest(e)) => Some(e),
+                        _ => None,
+                    }
+                })?;
+                if reqwest_error.is_timeout() || reqwest_error.is_connect() {
+                    Some(FetchFailure::Timeout)
+                } else if reqwest_error.is_status() {
+                    Some(FetchFailure::Other)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How long each kind of [FetchFailure] is cached. A duration of zero disables caching for
+/// that kind of failure.
+///
+/// Objects which are not found are not cached by default, because they may be created later
+/// under the same url.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NegativeCacheTtls {
+    /// Default zero, not cached
+    pub not_found: Duration,
+    /// Default 24 hours
+    pub deleted: Duration,
+    /// Default 5 minutes
+    pub timeout: Duration,
+    /// Default 1 hour
+    pub verification: Duration,
+    /// Default 5 minutes
+    pub other: Duration,
+}
+
+impl Default for NegativeCacheTtls {
+    fn default() -> Self {
+        NegativeCacheTtls {
+            not_found: Duration::ZERO,
+            deleted: Duration::from_secs(24 * 60 * 60),
+            timeout: Duration::from_secs(5 * 60),
+            verification: Duration::from_secs(60 * 60),
+            other: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl NegativeCacheTtls {
+    fn get(&self, failure: FetchFailure) -> Duration {
+        match failure {
+            FetchFailure::NotFound => self.not_found,
+            FetchFailure::Deleted => self.deleted,
+            FetchFailure::Timeout => self.timeout,
+            FetchFailure::Verification => self.verification,
+            FetchFailure::Other => self.other,
+        }
+    }
+}
+
+/// Remembers urls which recently failed to fetch, so that repeated references to them don't
+/// cause a new HTTP request every time.
+///
+/// It is consulted by [fetch_object_http](crate::fetch::fetch_object_http), which returns
+/// [Error::FetchFailedRecently] for cached failures. Configure it with
+/// [FederationConfigBuilder::negative_cache](crate::config::FederationConfigBuilder::negative_cache),
+/// and access it with [FederationConfig::negative_cache](crate::config::FederationConfig::negative_cache)
+/// to remove entries, for example after a remote server was fixed.
+#[derive(Clone)]
+pub struct NegativeCache {
+    cache: Cache<Url, CachedFailure>,
+    ttls: NegativeCacheTtls,
+}
+
+#[derive(Clone)]
+struct CachedFailure {
+    failure: FetchFailure,
+    ttl: Duration,
+    /// Only set for [FetchFailure::Deleted]
+    tombstone: Option<Box<Tombstone>>,
+}
+
+impl Default for NegativeCache {
+    fn default() -> Self {
+        NegativeCache::new(10000, NegativeCacheTtls::default())
+    }
+}
+
+impl NegativeCache {
+    /// Creates a new cache with at most `max_capacity` entries.
+    pub fn new(max_capacity: u64, ttls: NegativeCacheTtls) -> Self {
+        NegativeCache {
+            cache: Cache::builder()
+                .max_capacity(max_capacity)
+                .expire_after(FailureExpiry)
+                .build(),
+            ttls,
+        }
+    }
+
+    /// Returns the cached failure for this url, if any.
+    pub fn get(&self, url: &Url) -> Option<FetchFailure> {
+        self.cache.get(url).map(|cached| cached.failure)
+    }
+
+    /// Removes the cached failure for this url, so that it is fetched again.
+    pub async fn invalidate(&self, url: &Url) {
+        self.cache.invalidate(url).await
+    }
+
+    /// Removes all cached failures.
+    pub fn invalidate_all(&self) {
+        self.cache.invalidate_all()
+    }
+
+    /// Caches the error returned by a fetch, if it depends on the remote server.
+    pub(crate) async fn insert_error(&self, url: &Url, error: &Error) {
+        let Some(failure) = FetchFailure::from_error(error) else {
+            return;
+        };
+        let tombstone = match error {
+            Error::ObjectDeleted(_, tombstone) => Some(tombstone.clone()),
+            _ => None,
+        };
+        self.insert_with_tombstone(url, failure, tombstone).await
+    }
+
+    async fn insert_with_tombstone(
+        &self,
+        url: &Url,
+        failure: FetchFailure,
+        tombstone: Option<Box<Tombstone>>,
+    ) {
+        let ttl = self.ttls.get(failure);
+        if !ttl.is_zero() {
+            let cached = CachedFailure {
+                failure,
+                ttl,
+                tombstone,
+            };
+            self.cache.insert(url.clone(), cached).await;
+        }
+    }
+
+    /// Returns an error if fetching the url failed recently.
+    pub(crate) fn check(&self, url: &Url) -> Result<(), Error> {
+        match self.cache.get(url) {
+            Some(CachedFailure {
+                failure: FetchFailure::Deleted,
+                tombstone,
+                ..
+            }) => Err(Error::ObjectDeleted(
+                url.clone(),
+                tombstone.unwrap_or_default(),
+            )),
+            Some(cached) => Err(Error::FetchFailedRecently(url.clone(), cached.failure)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Expires each entry after the ttl of its failure kind
+struct FailureExpiry;
+
+impl Expiry<Url, CachedFailure> for FailureExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &Url,
+        value: &CachedFailure,
+        _current_time: Instant,
+    ) -> Option<Duration> {
+        Some(value.ttl)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &Url,
+        value: &CachedFailure,
+        _current_time: Instant,
+        _current_duration: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(value.ttl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_negative_cache() {
+        let cache = NegativeCache::new(
+            100,
+            NegativeCacheTtls {
+                not_found: Duration::from_secs(60),
+                timeout: Duration::ZERO,
+                ..Default::default()
+            },
+        );
+        let url = Url::parse("https://remote.com/objects/1").unwrap();
+        assert!(cache.check(&url).is_ok());
+
+        cache
+            .insert_error(&url, &Error::ObjectNotFound(url.clone()))
+            .await;
+        assert_eq!(Some(FetchFailure::NotFound), cache.get(&url));
+        match cache.check(&url) {
+            Err(Error::FetchFailedRecently(failed_url, FetchFailure::NotFound)) => {
+                assert_eq!(failed_url, url)
+            }
+            res => panic!("expected cached failure, got {res:?}"),
+        }
+
+        cache.invalidate(&url).await;
+        assert!(cache.check(&url).is_ok());
+
+        let tombstone = Tombstone {
+            former_type: Some("Note".to_string()),
+            deleted: None,
+        };
+        let error = Error::ObjectDeleted(url.clone(), Box::new(tombstone.clone()));
+        cache.insert_error(&url, &error).await;
+        match cache.check(&url) {
+            Err(Error::ObjectDeleted(_, cached)) => assert_eq!(*cached, tombstone),
+            res => panic!("expected deleted object, got {res:?}"),
+        }
+
+        // caching is disabled for timeouts
+        let other = Url::parse("https://remote.com/objects/2").unwrap();
+        cache
+            .insert_with_tombstone(&other, FetchFailure::Timeout, None)
+            .await;
+        assert_eq!(None, cache.get(&other));
+    }
+
+    #[tokio::test]
+    async fn test_failure_from_error() {
+        let url = Url::parse("https://remote.com/objects/1").unwrap();
+        assert_eq!(
+            FetchFailure::from_error(&Error::ObjectNotFound(url.clone())),
+            Some(FetchFailure::NotFound)
+        );
+
+        // errors of the remote server
+        let connect = reqwest::get("http://127.0.0.1:1").await.unwrap_err();
+        let connect = anyhow::Error::from(connect).context("fetching object");
+        assert_eq!(
+            FetchFailure::from_error(&Error::Other(connect)),
+            Some(FetchFailure::Timeout)
+        );
+        let mut response = http::Response::new("");
+        *response.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+        let status = reqwest::Response::from(response)
+            .error_for_status()
+            .unwrap_err();
+        assert_eq!(
+            FetchFailure::from_error(&Error::other(status)),
+            Some(FetchFailure::Other)
+        );
+
+        // local errors
+        let signer = anyhow::anyhow!("HSM unavailable").context("signing request");
+        assert_eq!(FetchFailure::from_error(&Error::Other(signer)), None);
+        let join = url.join("http://[invalid").unwrap_err();
+        assert_eq!(FetchFailure::from_error(&Error::other(join)), None);
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(FetchFailure::from_error(&Error::other(json)), None);
+        assert_eq!(
+            FetchFailure::from_error(&Error::RequestLimit(vec![url])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_not_found_not_cached_by_default() {
+        assert!(NegativeCacheTtls::default().not_found.is_zero());
+    }
+}
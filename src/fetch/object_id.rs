@@ -1,12 +1,23 @@
-use crate::{config::Data, error::Error, fetch::fetch_object_http, traits::Object};
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::{fetch_object_http_with_limit, DereferenceOptions, FetchBudget, FetchMode},
+    traits::Object,
+};
 use anyhow::anyhow;
 use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{HashMap, HashSet},
     fmt::{Debug, Display, Formatter},
     marker::PhantomData,
     str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, warn};
 use url::Url;
 
 impl<T> FromStr for ObjectId<T>
@@ -31,6 +42,14 @@ where
 /// [Error::RequestLimit]. This prevents denial of service attacks where an attack triggers
 /// infinite, recursive fetching of data.
 ///
+/// Objects which are dereferenced from [Object::verify] or [Object::from_json] of a fetched
+/// object are nested one level deeper. If this exceeds
+/// [FederationSettings.dereference_depth_limit], the request is aborted with [Error::DepthLimit].
+/// Both limits can be set for a single call with [ObjectId::dereference_with_budget].
+///
+/// [ObjectId::dereference_with_options] can be used to force a refetch, or to avoid HTTP
+/// requests entirely, see [DereferenceOptions].
+///
 /// ```
 /// # use activitypub_federation::fetch::object_id::ObjectId;
 /// # use activitypub_federation::config::FederationConfig;
@@ -92,31 +111,204 @@ where
     where
         <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
     {
-        let db_object = self.dereference_from_db(data).await?;
+        self.dereference_with_options(data, DereferenceOptions::default())
+            .await
+    }
+
+    /// Same as [ObjectId::dereference], but with custom limits for this object and all objects
+    /// which are dereferenced while verifying and parsing it.
+    pub async fn dereference_with_budget(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+        budget: FetchBudget,
+    ) -> Result<Kind, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        let options = DereferenceOptions::default().budget(budget);
+        self.dereference_with_options(data, options).await
+    }
+
+    /// Same as [ObjectId::dereference], but the options determine when the object is fetched
+    /// over http. Local objects are always read from the database only.
+    ///
+    /// ```
+    /// # use activitypub_federation::fetch::{object_id::ObjectId, DereferenceOptions};
+    /// # use activitypub_federation::config::FederationConfig;
+    /// # use activitypub_federation::traits::tests::{DbConnection, DbUser};
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// # let config = FederationConfig::builder().domain("example.com").app_data(DbConnection).build().await?;
+    /// # let data = config.to_request_data();
+    /// let object_id = ObjectId::<DbUser>::parse("https://lemmy.ml/u/nutomic")?;
+    /// // use the stored user, no matter how old it is
+    /// let options = DereferenceOptions::default().cache_only();
+    /// let user = object_id.dereference_with_options(&data, options).await?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// # }).unwrap();
+    /// ```
+    pub async fn dereference_with_options(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+        options: DereferenceOptions,
+    ) -> Result<Kind, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        let budget_data;
+        let data = match options.budget {
+            Some(budget) => {
+                budget_data = data.with_budget(budget);
+                &budget_data
+            }
+            None => data,
+        };
+        let id = self.canonical(data);
+        let db_object = id.dereference_from_db(data).await?;
+        id.dereference_db_object(data, db_object, options).await
+    }
+
+    /// Dereferences many objects at once, and returns the results in the same order as the ids.
+    ///
+    /// All objects are read from the database with a single call to [Object::read_from_ids].
+    /// Objects which are missing or stale are then fetched over http, with at most
+    /// [FederationConfigBuilder::batch_fetch_concurrency](crate::config::FederationConfigBuilder::batch_fetch_concurrency)
+    /// requests at the same time. All requests count against the request limit of `data`.
+    ///
+    /// The outer error is only returned if reading from the database fails.
+    pub async fn dereference_many<I>(
+        ids: I,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Vec<Result<Kind, <Kind as Object>::Error>>, <Kind as Object>::Error>
+    where
+        I: IntoIterator<Item = ObjectId<Kind>>,
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        let ids: Vec<ObjectId<Kind>> = ids.into_iter().map(|id| id.canonical(data)).collect();
+        let urls = ids.iter().map(|id| *id.0.clone()).collect();
+        let db_objects = Kind::read_from_ids(urls, data).await?;
+        if db_objects.len() != ids.len() {
+            return Err(anyhow!(
+                "read_from_ids returned {} objects for {} ids",
+                db_objects.len(),
+                ids.len()
+            )
+            .into());
+        }
+
+        let concurrency = data.config.batch_fetch_concurrency.max(1);
+        let results = stream::iter(ids.iter().zip(db_objects))
+            .map(|(id, db_object)| {
+                id.dereference_db_object(data, db_object, DereferenceOptions::default())
+            })
+            .buffered(concurrency)
+            .collect()
+            .await;
+        Ok(results)
+    }
+
+    /// Returns the object which was read from the database, or fetches it over http depending on
+    /// the options.
+    async fn dereference_db_object(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+        db_object: Option<Kind>,
+        options: DereferenceOptions,
+    ) -> Result<Kind, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
         // if its a local object, only fetch it from the database and not over http
-        if data.config.is_local_url(&self.0) {
+        if data.config.is_local_url(&self.0) || options.mode == FetchMode::NeverFetch {
             return match db_object {
                 None => Err(Error::NotFound.into()),
                 Some(o) => Ok(o),
             };
         }
 
-        // object found in database
-        if let Some(object) = db_object {
-            // object is old and should be refetched
-            if let Some(last_refreshed_at) = object.last_refreshed_at() {
-                if should_refetch_object(last_refreshed_at) {
-                    return self.dereference_from_http(data, Some(object)).await;
+        match db_object {
+            Some(object) => {
+                let refresh = match options.mode {
+                    FetchMode::ForceRefresh => {
+                        data.config.negative_cache.invalidate(&self.0).await;
+                        true
+                    }
+                    FetchMode::CacheOnly | FetchMode::NeverFetch => false,
+                    FetchMode::Default => is_stale(&object, data, options.max_age),
+                };
+                // object is old and should be refetched
+                if refresh {
+                    let force = options.mode == FetchMode::ForceRefresh;
+                    return self.dereference_from_http(data, Some(object), force).await;
                 }
+                Ok(object)
+            }
+            // object not found, need to fetch over http
+            None => {
+                if options.mode == FetchMode::ForceRefresh {
+                    data.config.negative_cache.invalidate(&self.0).await;
+                }
+                self.dereference_from_http(data, None, false).await
             }
-            Ok(object)
         }
-        // object not found, need to fetch over http
-        else {
-            self.dereference_from_http(data, None).await
+    }
+
+    /// Same as [ObjectId::dereference], but if the object in the local database is stale, it is
+    /// returned immediately and refreshed in the background.
+    ///
+    /// The refresh has its own request budget. It is skipped if the same object is already being
+    /// refreshed, or if the maximum number of background refreshes is running, see
+    /// [FederationConfigBuilder::background_refresh_limit](crate::config::FederationConfigBuilder::background_refresh_limit).
+    /// In that case the object is refreshed on one of the next calls.
+    pub async fn dereference_stale_while_revalidate(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Kind, <Kind as Object>::Error>
+    where
+        Kind: Sync,
+        <Kind as Object>::Kind: Send,
+        <Kind as Object>::DataType: 'static,
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error> + Display + Send,
+    {
+        let id = self.canonical(data);
+        let db_object = id.dereference_from_db(data).await?;
+        match db_object {
+            Some(object) => {
+                if !data.config.is_local_url(&id.0) && is_stale(&object, data, None) {
+                    id.refresh_in_background(data);
+                }
+                Ok(object)
+            }
+            None if data.config.is_local_url(&id.0) => Err(Error::NotFound.into()),
+            None => id.dereference_from_http(data, None, false).await,
         }
     }
 
+    fn refresh_in_background(&self, data: &Data<<Kind as Object>::DataType>)
+    where
+        Kind: Sync,
+        <Kind as Object>::Kind: Send,
+        <Kind as Object>::DataType: 'static,
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error> + Display + Send,
+    {
+        let Some(guard) = data.config.background_refresh.start(&self.0) else {
+            debug!("Skipping background refresh of {self}");
+            return;
+        };
+        let id = self.clone();
+        let data = data.reset_request_count();
+        tokio::spawn(async move {
+            let res = async {
+                let db_object = id.dereference_from_db(&data).await?;
+                id.dereference_from_http(&data, db_object, false).await
+            }
+            .await;
+            if let Err(e) = res {
+                warn!("Background refresh of {id} failed: {e}");
+            }
+            drop(guard);
+        });
+    }
+
     /// Fetch an object from the local db. Instead of falling back to http, this throws an error if
     /// the object is not found in the database.
     pub async fn dereference_local(
@@ -130,6 +322,15 @@ where
         object.ok_or_else(|| Error::NotFound.into())
     }
 
+    /// Returns the canonical id if the object was previously fetched from this url, but has
+    /// another id. See [fetch_object_http](crate::fetch::fetch_object_http).
+    fn canonical(&self, data: &Data<<Kind as Object>::DataType>) -> Self {
+        match data.config.canonical_ids.get(&self.0) {
+            Some(canonical_id) => canonical_id.into(),
+            None => self.clone(),
+        }
+    }
+
     /// returning none means the object was not found in local db
     async fn dereference_from_db(
         &self,
@@ -139,28 +340,127 @@ where
         Object::read_from_id(*id, data).await
     }
 
+    /// Concurrent calls for the same url are coalesced, so that only the first one fetches the
+    /// object and the others read it from the database afterwards. They wait until the first one
+    /// is finished, including nested fetches while parsing, or until it is cancelled.
+    ///
+    /// With `force`, the cache validators of `db_object` are not sent, so the object is always
+    /// parsed again.
     async fn dereference_from_http(
         &self,
         data: &Data<<Kind as Object>::DataType>,
         db_object: Option<Kind>,
+        force: bool,
     ) -> Result<Kind, <Kind as Object>::Error>
     where
         <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
     {
-        let res = fetch_object_http(&self.0, data).await;
+        // waiting for a fetch of the same url further up in the chain would never finish
+        if data.dereference_chain.contains(&self.0) {
+            return self
+                .fetch_and_parse(data, db_object, force)
+                .await
+                .map_err(FetchError::into_object_error);
+        }
+        match data.config.in_flight_fetches.join(&self.0) {
+            InFlightFetch::Leader(guard) => {
+                let res = self.fetch_and_parse(data, db_object, force).await;
+                guard.finish(match &res {
+                    Ok(_) => Ok(()),
+                    Err(FetchError::Library(e)) => Err(Some(e.duplicate())),
+                    Err(FetchError::Object(_)) => Err(None),
+                });
+                res.map_err(FetchError::into_object_error)
+            }
+            InFlightFetch::Follower(mut receiver) => {
+                // each request of the first fetch has a timeout, so this always finishes
+                let result = match receiver.wait_for(Option::is_some).await {
+                    Ok(result) => result.as_ref().map(|result| match result {
+                        Ok(()) => Ok(()),
+                        Err(e) => Err(e.as_ref().map(Error::duplicate)),
+                    }),
+                    // the first fetch was cancelled, so fetch it independently
+                    Err(_) => None,
+                };
+                match result {
+                    Some(Ok(())) => {
+                        if let Some(object) = self.dereference_from_db(data).await? {
+                            return Ok(object);
+                        }
+                    }
+                    Some(Err(Some(e))) => return Err(e.into()),
+                    Some(Err(None)) => {
+                        return Err(anyhow!(
+                            "Concurrent fetch of remote object {self} was rejected"
+                        )
+                        .into())
+                    }
+                    None => {}
+                }
+                self.fetch_and_parse(data, db_object, force)
+                    .await
+                    .map_err(FetchError::into_object_error)
+            }
+        }
+    }
+
+    async fn fetch_and_parse(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+        db_object: Option<Kind>,
+        force: bool,
+    ) -> Result<Kind, FetchError<<Kind as Object>::Error>>
+    where
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        let data = &data.dereference_nested(&self.0)?;
+        let validators = db_object
+            .as_ref()
+            .filter(|_| !force)
+            .and_then(Object::cache_validators);
+        let res = fetch_object_http_with_limit(
+            &self.0,
+            data,
+            data.response_size_limits.object,
+            validators.as_ref(),
+        )
+        .await;
 
-        if let Err(Error::ObjectDeleted) = &res {
+        if let Err(Error::NotModified) = &res {
             if let Some(db_object) = db_object {
-                db_object.delete(data).await?;
+                debug!("Remote object {self} was not modified");
+                return db_object
+                    .mark_refreshed(data)
+                    .await
+                    .map_err(FetchError::Object);
             }
-            return Err(anyhow!("Fetched remote object {} which was deleted", self).into());
         }
-
-        let res = res?;
+        let res = match res {
+            Err(Error::ObjectDeleted(url, tombstone)) => {
+                if let Some(db_object) = db_object {
+                    debug!("Remote object {self} was deleted");
+                    db_object.delete(data).await.map_err(FetchError::Object)?;
+                }
+                return Err(Error::ObjectDeleted(url, tombstone).into());
+            }
+            res => res?,
+        };
         let redirect_url = &res.url;
 
-        Kind::verify(&res.object, redirect_url, data).await?;
-        Kind::from_json(res.object, data).await
+        // not cached on failure, because the same url may be fetched as another type
+        Kind::verify(&res.object, redirect_url, data)
+            .await
+            .map_err(FetchError::Object)?;
+        let object = Kind::from_json(res.object, data)
+            .await
+            .map_err(FetchError::Object)?;
+        if res.cache_validators.is_empty() {
+            return Ok(object);
+        }
+        object
+            .set_cache_validators(res.cache_validators, data)
+            .await
+            .map_err(FetchError::Object)
     }
 }
 
@@ -175,21 +475,160 @@ where
     }
 }
 
-static ACTOR_REFETCH_INTERVAL_SECONDS: i64 = 24 * 60 * 60;
-static ACTOR_REFETCH_INTERVAL_SECONDS_DEBUG: i64 = 20;
-
-/// Determines when a remote actor should be refetched from its instance. In release builds, this is
-/// `ACTOR_REFETCH_INTERVAL_SECONDS` after the last refetch, in debug builds
-/// `ACTOR_REFETCH_INTERVAL_SECONDS_DEBUG`.
-fn should_refetch_object(last_refreshed: DateTime<Utc>) -> bool {
-    let update_interval = if cfg!(debug_assertions) {
-        // avoid infinite loop when fetching community outbox
-        ChronoDuration::seconds(ACTOR_REFETCH_INTERVAL_SECONDS_DEBUG)
-    } else {
-        ChronoDuration::seconds(ACTOR_REFETCH_INTERVAL_SECONDS)
-    };
-    let refresh_limit = Utc::now() - update_interval;
-    last_refreshed.lt(&refresh_limit)
+/// Checks if the object was refreshed longer ago than `max_age`, or its refetch interval if not
+/// given.
+fn is_stale<Kind: Object>(
+    object: &Kind,
+    data: &Data<<Kind as Object>::DataType>,
+    max_age: Option<Duration>,
+) -> bool {
+    match object.last_refreshed_at() {
+        Some(last_refreshed_at) => {
+            let interval = max_age
+                .or_else(|| object.refetch_interval())
+                .unwrap_or(data.config.refetch_interval);
+            should_refetch_object(last_refreshed_at, interval)
+        }
+        None => false,
+    }
+}
+
+/// Limits the number of objects which are refreshed in the background at the same time, and
+/// makes sure that each object is only refreshed once at a time.
+#[derive(Clone)]
+pub(crate) struct BackgroundRefresh {
+    permits: Arc<Semaphore>,
+    in_flight: Arc<Mutex<HashSet<Url>>>,
+}
+
+impl BackgroundRefresh {
+    pub(crate) fn new(limit: usize) -> Self {
+        BackgroundRefresh {
+            permits: Arc::new(Semaphore::new(limit)),
+            in_flight: Default::default(),
+        }
+    }
+
+    /// Returns `None` if the url is already being refreshed, or if the limit is reached. The
+    /// url is marked as in flight until the guard is dropped.
+    fn start(&self, url: &Url) -> Option<BackgroundRefreshGuard> {
+        let mut in_flight = self.in_flight.lock().expect("lock is not poisoned");
+        if in_flight.contains(url) {
+            return None;
+        }
+        let permit = self.permits.clone().try_acquire_owned().ok()?;
+        in_flight.insert(url.clone());
+        Some(BackgroundRefreshGuard {
+            in_flight: self.in_flight.clone(),
+            url: url.clone(),
+            _permit: permit,
+        })
+    }
+}
+
+struct BackgroundRefreshGuard {
+    in_flight: Arc<Mutex<HashSet<Url>>>,
+    url: Url,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for BackgroundRefreshGuard {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(&self.url);
+        }
+    }
+}
+
+/// Error while fetching and parsing an object. Errors of this library are kept apart from
+/// errors of the application, so that they can be passed to concurrent fetches.
+enum FetchError<E> {
+    Library(Error),
+    Object(E),
+}
+
+impl<E> From<Error> for FetchError<E> {
+    fn from(error: Error) -> Self {
+        FetchError::Library(error)
+    }
+}
+
+impl<E: From<Error>> FetchError<E> {
+    fn into_object_error(self) -> E {
+        match self {
+            FetchError::Library(e) => e.into(),
+            FetchError::Object(e) => e,
+        }
+    }
+}
+
+/// Result of a fetch which is passed to concurrent fetches of the same url. The error is `None`
+/// if the object was rejected by the application, because its error type can't be copied.
+type SharedFetchResult = Result<(), Option<Error>>;
+
+/// Dereferences of remote objects which are currently running, so that concurrent calls for the
+/// same url can wait for the result instead of fetching it again.
+#[derive(Clone, Default)]
+pub(crate) struct InFlightFetches {
+    /// Receives the result once the object was fetched and stored
+    fetches: Arc<Mutex<HashMap<Url, watch::Receiver<Option<SharedFetchResult>>>>>,
+}
+
+enum InFlightFetch {
+    /// No other fetch of the url is running, the caller needs to fetch it
+    Leader(InFlightFetchGuard),
+    /// Another fetch of the url is running
+    Follower(watch::Receiver<Option<SharedFetchResult>>),
+}
+
+impl InFlightFetches {
+    fn join(&self, url: &Url) -> InFlightFetch {
+        let mut fetches = self.fetches.lock().expect("lock is not poisoned");
+        if let Some(receiver) = fetches.get(url) {
+            return InFlightFetch::Follower(receiver.clone());
+        }
+        let (sender, receiver) = watch::channel(None);
+        fetches.insert(url.clone(), receiver);
+        InFlightFetch::Leader(InFlightFetchGuard {
+            fetches: self.fetches.clone(),
+            url: url.clone(),
+            sender,
+        })
+    }
+}
+
+/// Removes the url from in flight fetches when dropped, also if the fetch is cancelled.
+struct InFlightFetchGuard {
+    fetches: Arc<Mutex<HashMap<Url, watch::Receiver<Option<SharedFetchResult>>>>>,
+    url: Url,
+    sender: watch::Sender<Option<SharedFetchResult>>,
+}
+
+impl InFlightFetchGuard {
+    fn finish(self, result: SharedFetchResult) {
+        self.sender.send_replace(Some(result));
+    }
+}
+
+impl Drop for InFlightFetchGuard {
+    fn drop(&mut self) {
+        if let Ok(mut fetches) = self.fetches.lock() {
+            fetches.remove(&self.url);
+        }
+    }
+}
+
+/// Determines when a remote object should be refetched from its instance, which is `interval`
+/// after the last refetch.
+fn should_refetch_object(last_refreshed: DateTime<Utc>, interval: Duration) -> bool {
+    let refresh_limit = ChronoDuration::from_std(interval)
+        .ok()
+        .and_then(|interval| Utc::now().checked_sub_signed(interval));
+    match refresh_limit {
+        Some(refresh_limit) => last_refreshed.lt(&refresh_limit),
+        // interval is too large to represent, so the object never becomes stale
+        None => false,
+    }
 }
 
 impl<Kind> Display for ObjectId<Kind>
@@ -243,9 +682,187 @@ where
 }
 
 #[cfg(test)]
+/// Tests for [ObjectId]
 pub mod tests {
     use super::*;
-    use crate::{fetch::object_id::should_refetch_object, traits::tests::DbUser};
+    use crate::{
+        config::{FederationConfig, FederationConfigBuilder},
+        fetch::{
+            fetch_object_http,
+            object_id::should_refetch_object,
+            tests::{local_networks, spawn_test_server},
+            CacheValidators,
+        },
+        protocol::verification::verify_domains_match,
+        traits::tests::{DbConnection, DbUser},
+    };
+    use async_trait::async_trait;
+    use axum::{
+        extract::{Host, Path},
+        response::IntoResponse,
+        routing::get,
+        Json,
+        Router,
+    };
+    use http::{
+        header::{ETAG, IF_NONE_MATCH},
+        HeaderMap,
+        StatusCode,
+    };
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    /// In-memory database for [TestObject], which records the calls to object hooks
+    #[derive(Clone, Default)]
+    struct TestDb {
+        objects: Arc<Mutex<HashMap<Url, TestObject>>>,
+        calls: Arc<Mutex<Vec<(&'static str, Url)>>>,
+    }
+
+    impl TestDb {
+        fn insert(&self, object: TestObject) {
+            let mut objects = self.objects.lock().unwrap();
+            objects.insert(object.id.clone(), object);
+        }
+
+        fn get(&self, id: &Url) -> Option<TestObject> {
+            self.objects.lock().unwrap().get(id).cloned()
+        }
+
+        fn record(&self, hook: &'static str, id: &Url) {
+            self.calls.lock().unwrap().push((hook, id.clone()));
+        }
+
+        /// Returns how often the hook was called
+        fn calls(&self, hook: &str) -> usize {
+            let calls = self.calls.lock().unwrap();
+            calls.iter().filter(|(h, _)| *h == hook).count()
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct TestObject {
+        id: Url,
+        last_refreshed_at: Option<DateTime<Utc>>,
+        cache_validators: Option<CacheValidators>,
+    }
+
+    impl TestObject {
+        fn new(id: Url, last_refreshed_at: DateTime<Utc>) -> Self {
+            TestObject {
+                id,
+                last_refreshed_at: Some(last_refreshed_at),
+                cache_validators: None,
+            }
+        }
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct TestJson {
+        id: Url,
+        /// Dereferenced while parsing the object
+        parent: Option<ObjectId<TestObject>>,
+    }
+
+    #[async_trait]
+    impl Object for TestObject {
+        type DataType = TestDb;
+        type Kind = TestJson;
+        type Error = anyhow::Error;
+
+        fn last_refreshed_at(&self) -> Option<DateTime<Utc>> {
+            self.last_refreshed_at
+        }
+
+        fn cache_validators(&self) -> Option<CacheValidators> {
+            self.cache_validators.clone()
+        }
+
+        async fn set_cache_validators(
+            mut self,
+            validators: CacheValidators,
+            data: &Data<TestDb>,
+        ) -> Result<Self, Self::Error> {
+            self.cache_validators = Some(validators);
+            data.insert(self.clone());
+            Ok(self)
+        }
+
+        async fn mark_refreshed(mut self, data: &Data<TestDb>) -> Result<Self, Self::Error> {
+            data.record("mark_refreshed", &self.id);
+            self.last_refreshed_at = Some(Utc::now());
+            data.insert(self.clone());
+            Ok(self)
+        }
+
+        async fn read_from_id(id: Url, data: &Data<TestDb>) -> Result<Option<Self>, Self::Error> {
+            Ok(data.get(&id))
+        }
+
+        async fn delete(self, data: &Data<TestDb>) -> Result<(), Self::Error> {
+            data.record("delete", &self.id);
+            data.objects.lock().unwrap().remove(&self.id);
+            Ok(())
+        }
+
+        async fn into_json(self, _data: &Data<TestDb>) -> Result<TestJson, Self::Error> {
+            Ok(TestJson {
+                id: self.id,
+                parent: None,
+            })
+        }
+
+        async fn verify(
+            json: &TestJson,
+            expected_domain: &Url,
+            data: &Data<TestDb>,
+        ) -> Result<(), Self::Error> {
+            data.record("verify", &json.id);
+            verify_domains_match(&json.id, expected_domain)?;
+            Ok(())
+        }
+
+        async fn from_json(json: TestJson, data: &Data<TestDb>) -> Result<Self, Self::Error> {
+            data.record("from_json", &json.id);
+            if let Some(parent) = &json.parent {
+                parent.dereference(data).await?;
+            }
+            let object = TestObject::new(json.id, Utc::now());
+            data.insert(object.clone());
+            Ok(object)
+        }
+    }
+
+    async fn test_data(db: TestDb) -> Data<TestDb> {
+        test_config(db).build().await.unwrap().to_request_data()
+    }
+
+    fn test_config(db: TestDb) -> FederationConfigBuilder<TestDb> {
+        let mut config = FederationConfig::builder();
+        config
+            .domain("example.com")
+            .app_data(db)
+            .debug(true)
+            .ssrf_allowlist(local_networks());
+        config
+    }
+
+    /// Serves objects at the given paths, each of which has the next one as parent
+    fn serve_chain(paths: &'static [&'static str]) -> Url {
+        spawn_test_server(|base| {
+            let mut router = Router::new();
+            for (i, path) in paths.iter().enumerate() {
+                let parent = paths.get(i + 1).map(|p| base.join(p).unwrap());
+                let json = Json(json!({ "id": base.join(path).unwrap(), "parent": parent }));
+                router = router.route(path, get(move || async move { json }));
+            }
+            router
+        })
+    }
+
+    fn stale() -> DateTime<Utc> {
+        Utc::now() - ChronoDuration::days(7)
+    }
 
     #[test]
     fn test_deserialize() {
@@ -260,10 +877,522 @@ pub mod tests {
 
     #[test]
     fn test_should_refetch_object() {
+        let day = Duration::from_secs(24 * 60 * 60);
         let one_second_ago = Utc::now() - ChronoDuration::seconds(1);
-        assert!(!should_refetch_object(one_second_ago));
+        assert!(!should_refetch_object(one_second_ago, day));
+        assert!(should_refetch_object(one_second_ago, Duration::ZERO));
 
         let two_days_ago = Utc::now() - ChronoDuration::days(2);
-        assert!(should_refetch_object(two_days_ago));
+        assert!(should_refetch_object(two_days_ago, day));
+        assert!(!should_refetch_object(two_days_ago, day * 7));
+        assert!(!should_refetch_object(two_days_ago, Duration::MAX));
+    }
+
+    #[test]
+    fn test_background_refresh_limit() {
+        let refresh = BackgroundRefresh::new(2);
+        let first = Url::parse("https://remote.com/objects/1").unwrap();
+        let second = Url::parse("https://remote.com/objects/2").unwrap();
+        let third = Url::parse("https://remote.com/objects/3").unwrap();
+
+        let guard = refresh.start(&first);
+        assert!(guard.is_some());
+        // already in flight
+        assert!(refresh.start(&first).is_none());
+        let _second_guard = refresh.start(&second);
+        // limit reached
+        assert!(refresh.start(&third).is_none());
+
+        drop(guard);
+        assert!(refresh.start(&first).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_fetches() {
+        let fetches = InFlightFetches::default();
+        let url = Url::parse("https://remote.com/objects/1").unwrap();
+
+        let InFlightFetch::Leader(guard) = fetches.join(&url) else {
+            panic!("first fetch should be leader");
+        };
+        let InFlightFetch::Follower(mut receiver) = fetches.join(&url) else {
+            panic!("concurrent fetch should be follower");
+        };
+        let other = Url::parse("https://remote.com/objects/2").unwrap();
+        assert!(matches!(fetches.join(&other), InFlightFetch::Leader(_)));
+
+        let waiting = tokio::spawn(async move {
+            let result = receiver.wait_for(Option::is_some).await.unwrap();
+            result.as_ref().map(Result::is_ok)
+        });
+        guard.finish(Ok(()));
+        assert_eq!(Some(true), waiting.await.unwrap());
+
+        // once finished, the next fetch starts a new request
+        assert!(matches!(fetches.join(&url), InFlightFetch::Leader(_)));
+    }
+
+    #[tokio::test]
+    async fn test_dereference_coalesced() -> Result<(), anyhow::Error> {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_ = requests.clone();
+        let base = spawn_test_server(move |base| {
+            let json = Json(json!({ "id": base.join("/object").unwrap() }));
+            // responses are delayed, so that all dereferences start before the first one ends
+            let delayed = move || {
+                requests_.fetch_add(1, AtomicOrdering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(200))
+            };
+            let delayed_ = delayed.clone();
+            Router::new()
+                .route(
+                    "/object",
+                    get(move || async move {
+                        delayed().await;
+                        json
+                    }),
+                )
+                .route(
+                    "/missing",
+                    get(move || async move {
+                        delayed_().await;
+                        StatusCode::NOT_FOUND
+                    }),
+                )
+        });
+        let db = TestDb::default();
+        let config = test_config(db.clone()).build().await?;
+        let dereference_all = |path: &str| {
+            let id = ObjectId::<TestObject>::from(base.join(path).unwrap());
+            let config = config.clone();
+            async move {
+                let dereferences = (0..5).map(|_| {
+                    let id = id.clone();
+                    let data = config.to_request_data();
+                    async move { id.dereference(&data).await }
+                });
+                futures::future::join_all(dereferences).await
+            }
+        };
+
+        let results = dereference_all("/object").await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(1, requests.load(AtomicOrdering::SeqCst));
+        assert_eq!(1, db.calls("from_json"));
+
+        // all dereferences get the error of the first one
+        let results = dereference_all("/missing").await;
+        for res in results {
+            let err = res.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::ObjectNotFound(_))
+            ));
+        }
+        assert_eq!(2, requests.load(AtomicOrdering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_coalesced_slow_parse() -> Result<(), anyhow::Error> {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_ = requests.clone();
+        let base = spawn_test_server(move |base| {
+            let child = json!({ "id": base.join("/child").unwrap(), "parent": base.join("/parent").unwrap() });
+            let parent = json!({ "id": base.join("/parent").unwrap() });
+            let delayed = move |json: serde_json::Value| {
+                let requests = requests_.clone();
+                move || async move {
+                    requests.fetch_add(1, AtomicOrdering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Json(json)
+                }
+            };
+            Router::new()
+                .route("/child", get(delayed(child)))
+                .route("/parent", get(delayed(parent)))
+        });
+        let db = TestDb::default();
+        // each request finishes in time, but fetching the child with its parent takes longer
+        let config = test_config(db.clone())
+            .request_timeout(Duration::from_millis(300))
+            .build()
+            .await?;
+        let id = ObjectId::<TestObject>::from(base.join("/child")?);
+
+        let dereferences = (0..3).map(|_| {
+            let id = id.clone();
+            let data = config.to_request_data();
+            async move { id.dereference(&data).await }
+        });
+        let results = futures::future::join_all(dereferences).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(2, requests.load(AtomicOrdering::SeqCst));
+        assert_eq!(2, db.calls("from_json"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_alias() -> Result<(), anyhow::Error> {
+        let base = spawn_test_server(|base| {
+            let object = Json(json!({ "id": base.join("/object").unwrap() }));
+            let alias = object.clone();
+            Router::new()
+                .route("/object", get(move || async move { object }))
+                .route("/alias", get(move || async move { alias }))
+        });
+        let db = TestDb::default();
+        let data = test_data(db.clone()).await;
+        let id = ObjectId::<TestObject>::from(base.join("/alias")?);
+
+        // fetched from the alias, then again from its canonical id where it is stored
+        let object = id.dereference(&data).await?;
+        assert_eq!(base.join("/object")?, object.id);
+        assert_eq!(2, data.request_count());
+
+        // the alias is resolved to the canonical id, which is read from the database
+        let object = id.dereference(&data).await?;
+        assert_eq!(base.join("/object")?, object.id);
+        assert_eq!(2, data.request_count());
+        assert_eq!(1, db.calls("from_json"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_not_modified() -> Result<(), anyhow::Error> {
+        let base = spawn_test_server(|base| {
+            let id = base.join("/object").unwrap();
+            let object = |headers: HeaderMap| async move {
+                if headers.get(IF_NONE_MATCH).and_then(|h| h.to_str().ok()) == Some("\"v1\"") {
+                    return StatusCode::NOT_MODIFIED.into_response();
+                }
+                ([(ETAG, "\"v1\"")], Json(json!({ "id": id }))).into_response()
+            };
+            Router::new().route("/object", get(object))
+        });
+        let db = TestDb::default();
+        let data = test_data(db.clone()).await;
+        let url = base.join("/object")?;
+        let id = ObjectId::<TestObject>::from(url.clone());
+
+        // without validators the object is fetched and parsed, and the validators are stored
+        db.insert(TestObject::new(url.clone(), stale()));
+        id.dereference(&data).await?;
+        assert_eq!(1, db.calls("verify"));
+        assert_eq!(1, db.calls("from_json"));
+        let validators = db.get(&url).unwrap().cache_validators.unwrap();
+        assert_eq!(Some("\"v1\""), validators.etag.as_deref());
+
+        // with validators the server responds with 304, so the object is only marked as refreshed
+        let mut object = db.get(&url).unwrap();
+        object.last_refreshed_at = Some(stale());
+        db.insert(object);
+        let object = id.dereference(&data).await?;
+        assert_eq!(1, db.calls("mark_refreshed"));
+        assert_eq!(1, db.calls("verify"));
+        assert_eq!(1, db.calls("from_json"));
+        assert!(!is_stale(&object, &data, None));
+        assert_eq!(2, data.request_count());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_force_refresh_not_modified() -> Result<(), anyhow::Error> {
+        let base = spawn_test_server(|base| {
+            let id = base.join("/object").unwrap();
+            let object = |headers: HeaderMap| async move {
+                if headers.contains_key(IF_NONE_MATCH) {
+                    return StatusCode::NOT_MODIFIED.into_response();
+                }
+                ([(ETAG, "\"v1\"")], Json(json!({ "id": id }))).into_response()
+            };
+            Router::new().route("/object", get(object))
+        });
+        let db = TestDb::default();
+        let data = test_data(db.clone()).await;
+        let url = base.join("/object")?;
+        let id = ObjectId::<TestObject>::from(url.clone());
+        let mut object = TestObject::new(url.clone(), Utc::now());
+        object.cache_validators = Some(CacheValidators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        });
+        db.insert(object);
+
+        // validators are not sent when forcing, so the object is parsed again instead of a 304
+        id.dereference_with_options(&data, DereferenceOptions::default().force_refresh())
+            .await?;
+        assert_eq!(0, db.calls("mark_refreshed"));
+        assert_eq!(1, db.calls("verify"));
+        assert_eq!(1, db.calls("from_json"));
+        assert_eq!(1, data.request_count());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_stale_while_revalidate() -> Result<(), anyhow::Error> {
+        let base = spawn_test_server(|base| {
+            let json = Json(json!({ "id": base.join("/object").unwrap() }));
+            let object = move || async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                json
+            };
+            Router::new().route("/object", get(object))
+        });
+        let db = TestDb::default();
+        let data = test_data(db.clone()).await;
+        let url = base.join("/object")?;
+        let last_refreshed_at = stale();
+        db.insert(TestObject::new(url.clone(), last_refreshed_at));
+
+        // the stale object is returned without waiting for the slow response
+        let id = ObjectId::<TestObject>::from(url.clone());
+        let object = tokio::time::timeout(
+            Duration::from_millis(100),
+            id.dereference_stale_while_revalidate(&data),
+        )
+        .await??;
+        assert_eq!(Some(last_refreshed_at), object.last_refreshed_at);
+        assert_eq!(0, data.request_count());
+
+        // meanwhile it is refetched in the background
+        for _ in 0..50 {
+            if db.calls("from_json") > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(1, db.calls("from_json"));
+        let refreshed = db.get(&url).unwrap();
+        assert!(refreshed.last_refreshed_at > Some(last_refreshed_at));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_options() -> Result<(), anyhow::Error> {
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
+        let id = ObjectId::<DbUser>::parse("https://remote.com/u/alice")?;
+        // requests would fail with this budget, so these are served from the database
+        let no_requests = DereferenceOptions::default().budget(FetchBudget {
+            max_depth: 10,
+            max_requests: 0,
+        });
+
+        id.dereference_with_options(&data, no_requests).await?;
+        id.dereference_with_options(&data, no_requests.cache_only())
+            .await?;
+        id.dereference_with_options(&data, no_requests.never_fetch())
+            .await?;
+
+        let err = id
+            .dereference_with_options(&data, no_requests.force_refresh())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::RequestLimit(_))
+        ));
+        assert_eq!(1, data.request_count());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_many() -> Result<(), anyhow::Error> {
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
+        let ids = [
+            ObjectId::<DbUser>::parse("https://remote.com/u/alice")?,
+            ObjectId::<DbUser>::parse("https://other.com/u/bob")?,
+            ObjectId::<DbUser>::parse("https://example.com/u/local")?,
+        ];
+
+        let results = ObjectId::dereference_many(ids, &data).await?;
+        assert_eq!(3, results.len());
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(0, data.request_count());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_many_fetch() -> Result<(), anyhow::Error> {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let (in_flight_, max_in_flight_) = (in_flight.clone(), max_in_flight.clone());
+        let base = spawn_test_server(move |_| {
+            let object = |Host(host): Host, Path(name): Path<String>| async move {
+                let count = in_flight_.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+                max_in_flight_.fetch_max(count, AtomicOrdering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                in_flight_.fetch_sub(1, AtomicOrdering::SeqCst);
+                if name == "missing" {
+                    return StatusCode::NOT_FOUND.into_response();
+                }
+                let json = json!({ "id": format!("http://{host}/objects/{name}") });
+                Json(json).into_response()
+            };
+            Router::new().route("/objects/:name", get(object))
+        });
+        let db = TestDb::default();
+        let data = test_data(db.clone()).await.with_budget(FetchBudget {
+            max_depth: 10,
+            max_requests: 3,
+        });
+        let url = |name: &str| base.join(&format!("/objects/{name}")).unwrap();
+        db.insert(TestObject::new(url("stored"), Utc::now()));
+
+        let names = ["first", "stored", "second", "missing", "third", "fourth"];
+        let ids = names.map(|name| ObjectId::<TestObject>::from(url(name)));
+        let results = ObjectId::dereference_many(ids, &data).await?;
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|res| res.map(|object| object.id))
+            .collect();
+
+        assert_eq!(names.len(), results.len());
+        for (name, res) in names.into_iter().zip(&results).take(3) {
+            assert_eq!(&url(name), res.as_ref().unwrap());
+        }
+        let err = |i: usize| results[i].as_ref().unwrap_err().downcast_ref::<Error>();
+        assert!(matches!(err(3), Some(Error::ObjectNotFound(_))));
+        // the budget of three requests is shared by the whole batch
+        assert!(matches!(err(4), Some(Error::RequestLimit(_))));
+        assert!(matches!(err(5), Some(Error::RequestLimit(_))));
+        assert_eq!(2, db.calls("from_json"));
+        assert_eq!(5, data.request_count());
+        assert!(max_in_flight.load(AtomicOrdering::SeqCst) > 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_depth_limit() -> Result<(), anyhow::Error> {
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .build()
+            .await
+            .unwrap()
+            .to_request_data()
+            .with_budget(FetchBudget {
+                max_depth: 1,
+                max_requests: 10,
+            });
+        let first = Url::parse("https://remote.com/objects/1")?;
+        let second = Url::parse("https://remote.com/objects/2")?;
+
+        let nested = data.dereference_nested(&first)?;
+        let res = nested.dereference_nested(&second);
+        match res {
+            Err(Error::DepthLimit(chain)) => assert_eq!(chain, vec![first, second]),
+            _ => panic!("expected depth limit, got {:?}", res.err()),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_nested_depth_limit() -> Result<(), anyhow::Error> {
+        let base = serve_chain(&["/a", "/b", "/c"]);
+        let id = ObjectId::<TestObject>::from(base.join("/a")?);
+        let chain = ["/a", "/b", "/c"].map(|p| base.join(p).unwrap()).to_vec();
+
+        let db = TestDb::default();
+        let data = test_config(db.clone())
+            .dereference_depth_limit(2)
+            .build()
+            .await?
+            .to_request_data();
+        let err = id.dereference(&data).await.unwrap_err();
+        match err.downcast_ref::<Error>() {
+            Some(Error::DepthLimit(depth_chain)) => assert_eq!(depth_chain, &chain),
+            _ => panic!("expected depth limit, got {err}"),
+        }
+        // the limit was hit before /c was requested
+        assert_eq!(2, data.request_count());
+        assert!(db.get(&chain[0]).is_none());
+
+        let db = TestDb::default();
+        let data = test_config(db.clone())
+            .dereference_depth_limit(3)
+            .build()
+            .await?
+            .to_request_data();
+        id.dereference(&data).await?;
+        assert!(chain.iter().all(|id| db.get(id).is_some()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_limit_chain() -> Result<(), anyhow::Error> {
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
+        let first = Url::parse("https://remote.com/objects/1")?;
+        let second = Url::parse("https://remote.com/objects/2")?;
+        let nested = data.dereference_nested(&first)?.with_budget(FetchBudget {
+            max_depth: 10,
+            max_requests: 0,
+        });
+
+        let res = fetch_object_http::<_, serde_json::Value>(&second, &nested).await;
+        match res {
+            Err(Error::RequestLimit(chain)) => assert_eq!(chain, vec![first, second]),
+            _ => panic!("expected request limit, got {:?}", res.map(|r| r.object)),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dereference_foreign_tombstone() -> Result<(), anyhow::Error> {
+        let base = spawn_test_server(|base| {
+            let mut foreign_id = base.join("/object").unwrap();
+            foreign_id.set_host(Some("127.0.0.1")).unwrap();
+            let tombstone = Json(json!({ "id": foreign_id, "type": "Tombstone" }));
+            let own_tombstone =
+                Json(json!({ "id": base.join("/deleted").unwrap(), "type": "Tombstone" }));
+            Router::new()
+                .route("/foreign", get(move || async move { tombstone }))
+                .route("/deleted", get(move || async move { own_tombstone }))
+        });
+        let db = TestDb::default();
+        let data = test_data(db.clone()).await;
+        let foreign = base.join("/foreign")?;
+        let deleted = base.join("/deleted")?;
+        db.insert(TestObject::new(foreign.clone(), stale()));
+        db.insert(TestObject::new(deleted.clone(), stale()));
+
+        let res = ObjectId::<TestObject>::from(foreign.clone())
+            .dereference(&data)
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::ObjectNotFound(_))
+        ));
+        assert!(db.get(&foreign).is_some());
+        assert_eq!(0, db.calls("delete"));
+
+        let res = ObjectId::<TestObject>::from(deleted.clone())
+            .dereference(&data)
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::ObjectDeleted(..))
+        ));
+        assert!(db.get(&deleted).is_none());
+        assert_eq!(1, db.calls("delete"));
+        Ok(())
     }
 }
@@ -1,7 +1,7 @@
 use crate::{
     config::Data,
     error::{Error, Error::WebfingerResolveFailed},
-    fetch::{fetch_object_http_with_accept, object_id::ObjectId},
+    fetch::{fetch_object_http_with_kind, object_id::ObjectId, FetchKind},
     traits::{Actor, Object},
     FEDERATION_CONTENT_TYPE,
 };
@@ -36,10 +36,15 @@ where
         format!("{protocol}://{domain}/.well-known/webfinger?resource=acct:{identifier}");
     debug!("Fetching webfinger url: {}", &fetch_url);
 
-    let res: Webfinger =
-        fetch_object_http_with_accept(&Url::parse(&fetch_url)?, data, "application/jrd+json")
-            .await?
-            .object;
+    let res: Webfinger = fetch_object_http_with_kind(
+        &Url::parse(&fetch_url)?,
+        data,
+        FetchKind::Webfinger,
+        data.response_size_limits.webfinger,
+        None,
+    )
+    .await?
+    .object;
 
     debug_assert_eq!(res.subject, format!("acct:{identifier}"));
     let links: Vec<Url> = res
@@ -239,15 +244,13 @@ mod tests {
     #[tokio::test]
     async fn test_webfinger_extract_name() -> Result<(), Error> {
         use crate::traits::tests::DbConnection;
-        let data = Data {
-            config: FederationConfig::builder()
-                .domain("example.com")
-                .app_data(DbConnection)
-                .build()
-                .await
-                .unwrap(),
-            request_counter: Default::default(),
-        };
+        let data = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .build()
+            .await
+            .unwrap()
+            .to_request_data();
         assert_eq!(
             Ok("test123".to_string()),
             extract_webfinger_name("acct:test123@example.com", &data)
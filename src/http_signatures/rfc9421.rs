@@ -0,0 +1,447 @@
+//! Creating and verifying HTTP message signatures according to
+//! [RFC 9421](https://www.rfc-editor.org/rfc/rfc9421).
+//!
+//! Signatures are sent in the `Signature-Input` and `Signature` headers, and the request body is
+//! covered through the `Content-Digest` header.
+
+use super::{
+    signer::{Signer, ALG_ED25519, ALG_RSA_V1_5_SHA256},
+    structured_field::{
+        get_param,
+        parse_dictionary,
+        serialize_inner_list,
+        serialize_item,
+        BareItem,
+        Item,
+        ListEntry,
+        Parameters,
+    },
+    BodyDigest,
+    DigestAlgorithm,
+    DigestHeader,
+    EXPIRES_AFTER,
+};
+use crate::{error::Error, protocol::public_key::main_key_id};
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD as Base64, Engine};
+use bytes::Bytes;
+use http::{uri::PathAndQuery, HeaderValue, Method, Uri};
+use openssl::{
+    hash::MessageDigest,
+    pkey::{Id, PKey, Public},
+    rsa::Padding,
+    sign::{RsaPssSaltlen, Verifier},
+};
+use reqwest::Request;
+use reqwest_middleware::RequestBuilder;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+use url::Url;
+
+/// Label under which outgoing signatures are sent
+const SIGNATURE_LABEL: &str = "sig1";
+
+/// Maximum difference between local clock and `created` parameter of incoming signatures
+const CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+const ALG_RSA_PSS_SHA512: &str = "rsa-pss-sha512";
+
+/// Signs the request according to RFC 9421, with `body` as request body.
+///
+/// Covers `@method` and `@target-uri`, and `content-digest` if the body is not empty.
+pub(crate) async fn sign_request(
+    request_builder: RequestBuilder,
+    actor_id: &Url,
+    body: Bytes,
+    signer: &dyn Signer,
+    digest_algorithm: DigestAlgorithm,
+) -> Result<Request, anyhow::Error> {
+    let mut components = vec!["@method", "@target-uri"];
+    let request_builder = if body.is_empty() {
+        request_builder
+    } else {
+        components.push("content-digest");
+        let (name, digest) = BodyDigest {
+            header: DigestHeader::ContentDigest,
+            algorithm: digest_algorithm,
+        }
+        .header_value(&body);
+        request_builder.header(name, digest)
+    };
+    let mut request = request_builder.body(body).build()?;
+
+    let key_id = main_key_id(actor_id);
+    let created = unix_time(SystemTime::now())?;
+    let items: Vec<Item> = components
+        .iter()
+        .map(|c| Item {
+            bare_item: BareItem::String(c.to_string()),
+            params: vec![],
+        })
+        .collect();
+    let params: Parameters = vec![
+        ("created".to_string(), BareItem::Integer(created)),
+        (
+            "expires".to_string(),
+            BareItem::Integer(created + EXPIRES_AFTER.as_secs() as i64),
+        ),
+        ("keyid".to_string(), BareItem::String(key_id.clone())),
+        (
+            "alg".to_string(),
+            BareItem::String(signer.algorithm().to_string()),
+        ),
+    ];
+
+    let mut header_map = BTreeMap::<String, String>::new();
+    for (name, value) in request.headers() {
+        if let Ok(value) = value.to_str() {
+            header_map.insert(name.to_string(), value.to_string());
+        }
+    }
+    let message = MessageComponents {
+        method: request.method().as_str(),
+        target_uri: request.url(),
+        headers: &header_map,
+    };
+    let signature_params = serialize_inner_list(&items, &params);
+    let signature_base = message.signature_base(&items, &signature_params)?;
+
+    let signature = signer.sign(&key_id, &signature_base).await?;
+
+    let headers = request.headers_mut();
+    headers.insert(
+        "signature-input",
+        HeaderValue::from_str(&format!("{SIGNATURE_LABEL}={signature_params}"))?,
+    );
+    headers.insert(
+        "signature",
+        HeaderValue::from_str(&format!("{SIGNATURE_LABEL}=:{}:", Base64.encode(signature)))?,
+    );
+    Ok(request)
+}
+
+/// Returns the `keyid` parameter of the first signature in `Signature-Input` header.
+pub(crate) fn key_id(header_map: &BTreeMap<String, String>) -> Option<String> {
+    let (_, _, params) = find_signature(header_map).ok()?;
+    get_param(&params, "keyid")
+        .and_then(BareItem::as_str)
+        .map(ToString::to_string)
+}
+
+/// Verifies the RFC 9421 signature of an incoming request against `public_key`.
+///
+/// Incoming requests usually only contain the path, so `@target-uri` is reconstructed from the
+/// `Host` header. As the scheme is unknown in that case, both `https` and `http` are tried.
+pub(crate) fn verify_signature(
+    header_map: &BTreeMap<String, String>,
+    method: &Method,
+    uri: &Uri,
+    public_key: &PKey<Public>,
+) -> Result<(), Error> {
+    let (items, signature, params) = find_signature(header_map)?;
+
+    let now = unix_time(SystemTime::now())?;
+    let created = get_param(&params, "created")
+        .and_then(BareItem::as_integer)
+        .ok_or_else(|| Error::other(anyhow!("Signature is missing created parameter")))?;
+    if created > now + CLOCK_SKEW.as_secs() as i64 || created < now - EXPIRES_AFTER.as_secs() as i64
+    {
+        return Err(Error::other(anyhow!("Signature created time is invalid")));
+    }
+    if let Some(expires) = get_param(&params, "expires").and_then(BareItem::as_integer) {
+        if expires < now {
+            return Err(Error::other(anyhow!("Signature is expired")));
+        }
+    }
+
+    let covered: Vec<&str> = items.iter().filter_map(|i| i.bare_item.as_str()).collect();
+    if !covered.contains(&"@method")
+        || !["@target-uri", "@request-target", "@path"]
+            .iter()
+            .any(|c| covered.contains(c))
+    {
+        return Err(Error::other(anyhow!(
+            "Signature must cover method and target"
+        )));
+    }
+    if (header_map.contains_key("content-digest") || *method == Method::POST)
+        && !covered.contains(&"content-digest")
+    {
+        return Err(Error::other(anyhow!("Signature must cover content-digest")));
+    }
+
+    let alg = match get_param(&params, "alg").and_then(BareItem::as_str) {
+        Some(alg) => alg.to_string(),
+        None => match public_key.id() {
+            Id::RSA => ALG_RSA_V1_5_SHA256.to_string(),
+            Id::ED25519 => ALG_ED25519.to_string(),
+            id => return Err(Error::other(anyhow!("Unsupported key type {id:?}"))),
+        },
+    };
+    let signature_params = serialize_inner_list(&items, &params);
+
+    for target_uri in target_uri_candidates(header_map, uri)? {
+        let message = MessageComponents {
+            method: method.as_str(),
+            target_uri: &target_uri,
+            headers: header_map,
+        };
+        let signature_base = message
+            .signature_base(&items, &signature_params)
+            .map_err(Error::other)?;
+        debug!(
+            "Verifying with key {:?}, message {}",
+            &public_key, &signature_base
+        );
+        if verify_bytes(&alg, public_key, signature_base.as_bytes(), &signature)
+            .map_err(Error::other)?
+        {
+            debug!("verified signature for {}", uri);
+            return Ok(());
+        }
+    }
+    Err(Error::ActivitySignatureInvalid)
+}
+
+/// Finds the first signature which is present in both `Signature-Input` and `Signature` headers,
+/// and returns its covered components, signature bytes and parameters.
+fn find_signature(
+    header_map: &BTreeMap<String, String>,
+) -> Result<(Vec<Item>, Vec<u8>, Parameters), Error> {
+    let signature_input = header_map
+        .get("signature-input")
+        .ok_or(Error::ActivitySignatureInvalid)?;
+    let signature = header_map
+        .get("signature")
+        .ok_or(Error::ActivitySignatureInvalid)?;
+    let signature_input = parse_dictionary(signature_input).map_err(Error::other)?;
+    let signature = parse_dictionary(signature).map_err(Error::other)?;
+
+    signature_input
+        .into_iter()
+        .find_map(|(label, entry)| {
+            let ListEntry::InnerList(items, params) = entry else {
+                return None;
+            };
+            signature.iter().find_map(|(l, e)| match e {
+                ListEntry::Item(item) if l == &label => item
+                    .bare_item
+                    .as_byte_sequence()
+                    .map(|s| (items.clone(), s.to_vec(), params.clone())),
+                _ => None,
+            })
+        })
+        .ok_or(Error::ActivitySignatureInvalid)
+}
+
+/// Builds the possible values for `@target-uri` of an incoming request.
+fn target_uri_candidates(
+    header_map: &BTreeMap<String, String>,
+    uri: &Uri,
+) -> Result<Vec<Url>, Error> {
+    if uri.scheme().is_some() {
+        return Ok(vec![Url::parse(&uri.to_string()).map_err(Error::other)?]);
+    }
+    let authority = match uri.authority() {
+        Some(a) => a.as_str(),
+        None => header_map
+            .get("host")
+            .ok_or_else(|| Error::other(anyhow!("Request is missing host header")))?,
+    };
+    let path_and_query = uri
+        .path_and_query()
+        .map(PathAndQuery::as_str)
+        .unwrap_or("/");
+    ["https", "http"]
+        .iter()
+        .map(|scheme| {
+            Url::parse(&format!("{scheme}://{authority}{path_and_query}")).map_err(Error::other)
+        })
+        .collect()
+}
+
+fn verify_bytes(
+    alg: &str,
+    public_key: &PKey<Public>,
+    data: &[u8],
+    signature: &[u8],
+) -> Result<bool, anyhow::Error> {
+    match (alg, public_key.id()) {
+        (ALG_RSA_V1_5_SHA256, Id::RSA) => {
+            let mut verifier = Verifier::new(MessageDigest::sha256(), public_key)?;
+            verifier.update(data)?;
+            Ok(verifier.verify(signature)?)
+        }
+        (ALG_RSA_PSS_SHA512, Id::RSA) => {
+            let mut verifier = Verifier::new(MessageDigest::sha512(), public_key)?;
+            verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+            verifier.set_rsa_mgf1_md(MessageDigest::sha512())?;
+            verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+            verifier.update(data)?;
+            Ok(verifier.verify(signature)?)
+        }
+        (ALG_ED25519, Id::ED25519) => {
+            let mut verifier = Verifier::new_without_digest(public_key)?;
+            Ok(verifier.verify_oneshot(signature, data)?)
+        }
+        (alg, id) => Err(anyhow!("Unsupported algorithm {alg} for key type {id:?}")),
+    }
+}
+
+fn unix_time(time: SystemTime) -> Result<i64, Error> {
+    Ok(time
+        .duration_since(UNIX_EPOCH)
+        .map_err(Error::other)?
+        .as_secs() as i64)
+}
+
+/// Values which can be covered by a signature
+struct MessageComponents<'a> {
+    method: &'a str,
+    target_uri: &'a Url,
+    headers: &'a BTreeMap<String, String>,
+}
+
+impl MessageComponents<'_> {
+    /// Builds the signature base as described in
+    /// <https://www.rfc-editor.org/rfc/rfc9421#name-creating-the-signature-base>
+    fn signature_base(
+        &self,
+        items: &[Item],
+        signature_params: &str,
+    ) -> Result<String, anyhow::Error> {
+        let mut lines = vec![];
+        for item in items {
+            let name = item
+                .bare_item
+                .as_str()
+                .ok_or_else(|| anyhow!("Component identifier must be a string"))?;
+            if !item.params.is_empty() {
+                return Err(anyhow!("Component parameters are not supported"));
+            }
+            lines.push(format!("{}: {}", serialize_item(item), self.value(name)?));
+        }
+        lines.push(format!("\"@signature-params\": {signature_params}"));
+        Ok(lines.join("\n"))
+    }
+
+    fn value(&self, name: &str) -> Result<String, anyhow::Error> {
+        let url = self.target_uri;
+        Ok(match name {
+            "@method" => self.method.to_string(),
+            "@target-uri" => url.to_string(),
+            "@authority" => {
+                let host = url.host_str().unwrap_or_default().to_lowercase();
+                match url.port() {
+                    Some(port) => format!("{host}:{port}"),
+                    None => host,
+                }
+            }
+            "@scheme" => url.scheme().to_string(),
+            "@request-target" => match url.query() {
+                Some(query) => format!("{}?{query}", url.path()),
+                None => url.path().to_string(),
+            },
+            "@path" => url.path().to_string(),
+            "@query" => format!("?{}", url.query().unwrap_or_default()),
+            _ if name.starts_with('@') => {
+                return Err(anyhow!("Unsupported derived component {name}"))
+            }
+            _ => self
+                .headers
+                .get(name)
+                .map(|v| v.trim().to_string())
+                .ok_or_else(|| anyhow!("Covered header {name} is missing"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        activity_sending::generate_request_headers,
+        http_signatures::PemSigner,
+        traits::tests::DB_USER_KEYPAIR,
+    };
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+    use sha2::{Digest, Sha256};
+    use std::str::FromStr;
+
+    async fn signed_request(body: &'static str) -> Request {
+        let inbox_url = Url::parse("https://example.com/u/alice/inbox").unwrap();
+        let request_builder = ClientWithMiddleware::from(Client::new())
+            .post(inbox_url.to_string())
+            .headers(generate_request_headers(&inbox_url));
+        sign_request(
+            request_builder,
+            &Url::parse("https://example.com/u/alice").unwrap(),
+            body.into(),
+            &PemSigner::new(&DB_USER_KEYPAIR.private_key).unwrap(),
+            DigestAlgorithm::Sha256,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn header_map(request: &Request) -> BTreeMap<String, String> {
+        request
+            .headers()
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_str().unwrap().to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_sign_verify() {
+        let request = signed_request("my activity").await;
+        let header_map = header_map(&request);
+        assert_eq!(
+            key_id(&header_map),
+            Some("https://example.com/u/alice#main-key".to_string())
+        );
+
+        // verify with path only, like incoming requests
+        let uri = Uri::from_str(request.url().path()).unwrap();
+        let valid = verify_signature(
+            &header_map,
+            request.method(),
+            &uri,
+            &DB_USER_KEYPAIR.public_key().unwrap(),
+        );
+        assert!(valid.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_verify_wrong_path() {
+        let request = signed_request("my activity").await;
+        let invalid = verify_signature(
+            &header_map(&request),
+            request.method(),
+            &Uri::from_static("/wrong"),
+            &DB_USER_KEYPAIR.public_key().unwrap(),
+        );
+        assert_eq!(invalid, Err(Error::ActivitySignatureInvalid));
+    }
+
+    #[tokio::test]
+    async fn test_verify_modified_digest() {
+        let request = signed_request("my activity").await;
+        let mut header_map = header_map(&request);
+        header_map.insert(
+            "content-digest".to_string(),
+            format!("sha-256=:{}:", Base64.encode(Sha256::digest("other"))),
+        );
+        let invalid = verify_signature(
+            &header_map,
+            request.method(),
+            &Uri::from_str(request.url().path()).unwrap(),
+            &DB_USER_KEYPAIR.public_key().unwrap(),
+        );
+        assert_eq!(invalid, Err(Error::ActivitySignatureInvalid));
+    }
+}
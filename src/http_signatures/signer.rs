@@ -0,0 +1,218 @@
+//! Pluggable signing of outgoing HTTP requests
+//!
+//! [Signer] abstracts over the private key, so that keys can also be kept outside of the
+//! application. [PemSigner] is the default implementation for PEM encoded keys.
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use openssl::{
+    hash::MessageDigest,
+    pkey::{Id, PKey, Private},
+};
+use std::fmt::{Debug, Formatter};
+
+/// Creates signatures for outgoing HTTP requests.
+///
+/// By default requests are signed with the private key returned by
+/// [Actor::private_key_pem](crate::traits::Actor::private_key_pem), using [PemSigner]. Implement
+/// this trait and return it from [Actor::signer](crate::traits::Actor::signer) if private keys
+/// should never be loaded into the application, for example because they are held in an HSM or
+/// a key management service.
+///
+/// ```
+/// # use activitypub_federation::http_signatures::Signer;
+/// # struct KmsClient;
+/// # impl KmsClient {
+/// #     async fn sign(&self, _key: &str, _data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+/// #         Ok(vec![])
+/// #     }
+/// # }
+/// struct KmsSigner {
+///     client: KmsClient,
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl Signer for KmsSigner {
+///     async fn sign(&self, key_id: &str, signing_string: &str) -> Result<Vec<u8>, anyhow::Error> {
+///         self.client.sign(key_id, signing_string.as_bytes()).await
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// Signs `signing_string` with the private key which belongs to `key_id`, and returns the
+    /// raw signature bytes.
+    async fn sign(&self, key_id: &str, signing_string: &str) -> Result<Vec<u8>, anyhow::Error>;
+
+    /// Name of the signature algorithm, which is sent in the `alg` parameter of RFC 9421
+    /// signatures. Defaults to RSA PKCS#1 v1.5 with SHA-256, which is used by all major
+    /// fediverse platforms.
+    fn algorithm(&self) -> &str {
+        ALG_RSA_V1_5_SHA256
+    }
+}
+
+impl Debug for dyn Signer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Signer({})", self.algorithm())
+    }
+}
+
+pub(crate) const ALG_RSA_V1_5_SHA256: &str = "rsa-v1_5-sha256";
+pub(crate) const ALG_ED25519: &str = "ed25519";
+
+/// Default [Signer] which holds an RSA or Ed25519 private key in memory.
+#[derive(Clone, Debug)]
+pub struct PemSigner {
+    private_key: PKey<Private>,
+}
+
+impl PemSigner {
+    /// Create a signer from a private key in PEM format.
+    pub fn new(private_key_pem: &str) -> Result<Self, anyhow::Error> {
+        let private_key = PKey::private_key_from_pem(private_key_pem.as_bytes())
+            .map_err(|err| anyhow!("Could not create private key from PEM data: {err}"))?;
+        Ok(private_key.into())
+    }
+}
+
+impl From<PKey<Private>> for PemSigner {
+    fn from(private_key: PKey<Private>) -> Self {
+        PemSigner { private_key }
+    }
+}
+
+#[async_trait]
+impl Signer for PemSigner {
+    async fn sign(&self, _key_id: &str, signing_string: &str) -> Result<Vec<u8>, anyhow::Error> {
+        if self.private_key.id() == Id::ED25519 {
+            let mut signer = openssl::sign::Signer::new_without_digest(&self.private_key)?;
+            return Ok(signer.sign_oneshot_to_vec(signing_string.as_bytes())?);
+        }
+        let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), &self.private_key)
+            .context("instantiating signer")?;
+        signer
+            .update(signing_string.as_bytes())
+            .context("updating signer")?;
+        Ok(signer.sign_to_vec().context("sign to vec")?)
+    }
+
+    fn algorithm(&self) -> &str {
+        match self.private_key.id() {
+            Id::ED25519 => ALG_ED25519,
+            _ => ALG_RSA_V1_5_SHA256,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        activity_sending::generate_request_headers,
+        http_signatures::{
+            sign_request_with_scheme,
+            verify_signature,
+            BodyDigest,
+            HttpSignatureScheme,
+        },
+        traits::tests::DB_USER_KEYPAIR,
+    };
+    use http::Uri;
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+    use std::str::FromStr;
+    use tokio::sync::{mpsc, oneshot};
+    use url::Url;
+
+    type SignRequest = (String, String, oneshot::Sender<Result<Vec<u8>, String>>);
+
+    /// Stand-in for a remote signing service. The private key only exists inside a background
+    /// task, and signatures are requested over a channel.
+    pub(crate) struct ServiceSigner {
+        sender: mpsc::Sender<SignRequest>,
+    }
+
+    impl ServiceSigner {
+        pub(crate) fn spawn(key_id: String, private_key_pem: String) -> Self {
+            let (sender, mut receiver) = mpsc::channel::<SignRequest>(10);
+            tokio::spawn(async move {
+                let signer = PemSigner::new(&private_key_pem).unwrap();
+                while let Some((requested_key_id, signing_string, response)) = receiver.recv().await
+                {
+                    let result = if requested_key_id == key_id {
+                        signer
+                            .sign(&requested_key_id, &signing_string)
+                            .await
+                            .map_err(|e| e.to_string())
+                    } else {
+                        Err(format!("unknown key {requested_key_id}"))
+                    };
+                    let _ = response.send(result);
+                }
+            });
+            ServiceSigner { sender }
+        }
+    }
+
+    #[async_trait]
+    impl Signer for ServiceSigner {
+        async fn sign(&self, key_id: &str, signing_string: &str) -> Result<Vec<u8>, anyhow::Error> {
+            let (sender, receiver) = oneshot::channel();
+            self.sender
+                .send((key_id.to_string(), signing_string.to_string(), sender))
+                .await?;
+            receiver.await?.map_err(|e| anyhow!(e))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_service_signer() {
+        let actor_id = Url::parse("https://example.com/u/alice").unwrap();
+        let inbox_url = Url::parse("https://example.com/u/alice/inbox").unwrap();
+        let signer = ServiceSigner::spawn(
+            "https://example.com/u/alice#main-key".to_string(),
+            DB_USER_KEYPAIR.private_key.clone(),
+        );
+
+        for scheme in [HttpSignatureScheme::Cavage, HttpSignatureScheme::Rfc9421] {
+            let request_builder = ClientWithMiddleware::from(Client::new())
+                .post(inbox_url.to_string())
+                .headers(generate_request_headers(&inbox_url));
+            let request = sign_request_with_scheme(
+                scheme,
+                request_builder,
+                &actor_id,
+                "my activity".into(),
+                &signer,
+                false,
+                BodyDigest::default(),
+            )
+            .await
+            .unwrap();
+
+            let valid = verify_signature(
+                request.headers(),
+                request.method(),
+                &Uri::from_str(request.url().as_str()).unwrap(),
+                &DB_USER_KEYPAIR.public_key().unwrap(),
+            );
+            assert!(valid.is_ok(), "{scheme:?}: {valid:?}");
+        }
+
+        // signing with a key that the service doesn't know fails
+        let request_builder = ClientWithMiddleware::from(Client::new()).get(inbox_url.as_str());
+        let other_actor = Url::parse("https://example.com/u/bob").unwrap();
+        let res = sign_request_with_scheme(
+            HttpSignatureScheme::Cavage,
+            request_builder,
+            &other_actor,
+            Default::default(),
+            &signer,
+            false,
+            BodyDigest::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,392 @@
+//! Minimal parser and serializer for structured field values
+//!
+//! Only the subset of [RFC 8941](https://www.rfc-editor.org/rfc/rfc8941) which is needed for
+//! `Signature-Input`, `Signature` and `Content-Digest` headers is supported. Decimals are
+//! rejected.
+
+use base64::{engine::general_purpose::STANDARD as Base64, Engine};
+
+/// A single bare value, without parameters
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum BareItem {
+    Integer(i64),
+    String(String),
+    Token(String),
+    ByteSequence(Vec<u8>),
+    Boolean(bool),
+}
+
+/// Parameters attached to an item or inner list, in their original order
+pub(crate) type Parameters = Vec<(String, BareItem)>;
+
+/// A bare value together with its parameters
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Item {
+    pub bare_item: BareItem,
+    pub params: Parameters,
+}
+
+/// Value of a dictionary member
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ListEntry {
+    Item(Item),
+    InnerList(Vec<Item>, Parameters),
+}
+
+/// Dictionary members, in their original order
+pub(crate) type Dictionary = Vec<(String, ListEntry)>;
+
+impl BareItem {
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            BareItem::String(s) | BareItem::Token(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_integer(&self) -> Option<i64> {
+        match self {
+            BareItem::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_byte_sequence(&self) -> Option<&[u8]> {
+        match self {
+            BareItem::ByteSequence(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the value of parameter with the given `key`, if present.
+pub(crate) fn get_param<'a>(params: &'a Parameters, key: &str) -> Option<&'a BareItem> {
+    params.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// Parse a dictionary structured field, such as the value of a `Signature-Input` header.
+pub(crate) fn parse_dictionary(input: &str) -> Result<Dictionary, anyhow::Error> {
+    let mut parser = Parser {
+        input: input.as_bytes(),
+        pos: 0,
+    };
+    let mut dictionary: Dictionary = vec![];
+    parser.skip_sp();
+    while !parser.is_empty() {
+        let key = parser.parse_key()?;
+        let entry = if parser.consume(b'=') {
+            parser.parse_list_entry()?
+        } else {
+            ListEntry::Item(Item {
+                bare_item: BareItem::Boolean(true),
+                params: parser.parse_params()?,
+            })
+        };
+        // Duplicate keys overwrite earlier values
+        dictionary.retain(|(k, _)| k != &key);
+        dictionary.push((key, entry));
+
+        parser.skip_ows();
+        if parser.is_empty() {
+            break;
+        }
+        if !parser.consume(b',') {
+            anyhow::bail!("Expected comma in dictionary at position {}", parser.pos);
+        }
+        parser.skip_ows();
+        if parser.is_empty() {
+            anyhow::bail!("Trailing comma in dictionary");
+        }
+    }
+    Ok(dictionary)
+}
+
+/// Serialize an inner list with parameters, as used for the `@signature-params` component.
+pub(crate) fn serialize_inner_list(items: &[Item], params: &Parameters) -> String {
+    let mut out = String::from("(");
+    let items: Vec<_> = items.iter().map(serialize_item).collect();
+    out.push_str(&items.join(" "));
+    out.push(')');
+    out.push_str(&serialize_params(params));
+    out
+}
+
+/// Serialize a single item with its parameters.
+pub(crate) fn serialize_item(item: &Item) -> String {
+    let mut out = serialize_bare_item(&item.bare_item);
+    out.push_str(&serialize_params(&item.params));
+    out
+}
+
+fn serialize_params(params: &Parameters) -> String {
+    let mut out = String::new();
+    for (key, value) in params {
+        out.push(';');
+        out.push_str(key);
+        if value != &BareItem::Boolean(true) {
+            out.push('=');
+            out.push_str(&serialize_bare_item(value));
+        }
+    }
+    out
+}
+
+fn serialize_bare_item(item: &BareItem) -> String {
+    match item {
+        BareItem::Integer(i) => i.to_string(),
+        BareItem::String(s) => {
+            let mut out = String::from("\"");
+            for c in s.chars() {
+                if c == '"' || c == '\\' {
+                    out.push('\\');
+                }
+                out.push(c);
+            }
+            out.push('"');
+            out
+        }
+        BareItem::Token(t) => t.clone(),
+        BareItem::ByteSequence(b) => format!(":{}:", Base64.encode(b)),
+        BareItem::Boolean(true) => "?1".to_string(),
+        BareItem::Boolean(false) => "?0".to_string(),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn consume(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_sp(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_ows(&mut self) {
+        while matches!(self.peek(), Some(b' ') | Some(b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn parse_key(&mut self) -> Result<String, anyhow::Error> {
+        let start = self.pos;
+        match self.peek() {
+            Some(c) if c.is_ascii_lowercase() || c == b'*' => {}
+            _ => anyhow::bail!("Invalid key at position {}", self.pos),
+        }
+        while let Some(c) = self.peek() {
+            if c.is_ascii_lowercase() || c.is_ascii_digit() || b"_-.*".contains(&c) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        Ok(String::from_utf8_lossy(&self.input[start..self.pos]).to_string())
+    }
+
+    fn parse_list_entry(&mut self) -> Result<ListEntry, anyhow::Error> {
+        if self.peek() == Some(b'(') {
+            self.parse_inner_list()
+        } else {
+            Ok(ListEntry::Item(self.parse_item()?))
+        }
+    }
+
+    fn parse_inner_list(&mut self) -> Result<ListEntry, anyhow::Error> {
+        if !self.consume(b'(') {
+            anyhow::bail!("Expected inner list");
+        }
+        let mut items = vec![];
+        loop {
+            self.skip_sp();
+            if self.consume(b')') {
+                let params = self.parse_params()?;
+                return Ok(ListEntry::InnerList(items, params));
+            }
+            items.push(self.parse_item()?);
+            match self.peek() {
+                Some(b' ') | Some(b')') => {}
+                _ => anyhow::bail!("Invalid inner list at position {}", self.pos),
+            }
+        }
+    }
+
+    fn parse_item(&mut self) -> Result<Item, anyhow::Error> {
+        let bare_item = self.parse_bare_item()?;
+        let params = self.parse_params()?;
+        Ok(Item { bare_item, params })
+    }
+
+    fn parse_params(&mut self) -> Result<Parameters, anyhow::Error> {
+        let mut params: Parameters = vec![];
+        while self.consume(b';') {
+            self.skip_sp();
+            let key = self.parse_key()?;
+            let value = if self.consume(b'=') {
+                self.parse_bare_item()?
+            } else {
+                BareItem::Boolean(true)
+            };
+            params.retain(|(k, _)| k != &key);
+            params.push((key, value));
+        }
+        Ok(params)
+    }
+
+    fn parse_bare_item(&mut self) -> Result<BareItem, anyhow::Error> {
+        match self.peek() {
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_integer(),
+            Some(b'"') => self.parse_string(),
+            Some(b':') => self.parse_byte_sequence(),
+            Some(b'?') => self.parse_boolean(),
+            Some(c) if c.is_ascii_alphabetic() || c == b'*' => Ok(self.parse_token()),
+            _ => anyhow::bail!("Invalid bare item at position {}", self.pos),
+        }
+    }
+
+    fn parse_integer(&mut self) -> Result<BareItem, anyhow::Error> {
+        let start = self.pos;
+        self.consume(b'-');
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'.') {
+            anyhow::bail!("Decimals are not supported");
+        }
+        let digits = std::str::from_utf8(&self.input[start..self.pos])?;
+        if digits.trim_start_matches('-').len() > 15 {
+            anyhow::bail!("Integer too long");
+        }
+        Ok(BareItem::Integer(digits.parse()?))
+    }
+
+    fn parse_string(&mut self) -> Result<BareItem, anyhow::Error> {
+        self.consume(b'"');
+        let mut out = String::new();
+        loop {
+            match self.peek() {
+                None => anyhow::bail!("Unterminated string"),
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(c @ b'"') | Some(c @ b'\\') => out.push(c as char),
+                        _ => anyhow::bail!("Invalid escape in string"),
+                    }
+                }
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(BareItem::String(out));
+                }
+                Some(c @ 0x20..=0x7e) => out.push(c as char),
+                Some(_) => anyhow::bail!("Invalid character in string"),
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn parse_token(&mut self) -> BareItem {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~:/".contains(&c) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        BareItem::Token(String::from_utf8_lossy(&self.input[start..self.pos]).to_string())
+    }
+
+    fn parse_byte_sequence(&mut self) -> Result<BareItem, anyhow::Error> {
+        self.consume(b':');
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == b':' {
+                break;
+            }
+            self.pos += 1;
+        }
+        let encoded = &self.input[start..self.pos];
+        if !self.consume(b':') {
+            anyhow::bail!("Unterminated byte sequence");
+        }
+        Ok(BareItem::ByteSequence(Base64.decode(encoded)?))
+    }
+
+    fn parse_boolean(&mut self) -> Result<BareItem, anyhow::Error> {
+        self.consume(b'?');
+        if self.consume(b'1') {
+            Ok(BareItem::Boolean(true))
+        } else if self.consume(b'0') {
+            Ok(BareItem::Boolean(false))
+        } else {
+            anyhow::bail!("Invalid boolean at position {}", self.pos)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signature_input() {
+        let input = r#"sig1=("@method" "@target-uri" "content-digest");created=1618884473;keyid="https://example.com/u/alice#main-key""#;
+        let dictionary = parse_dictionary(input).unwrap();
+        assert_eq!(dictionary.len(), 1);
+        let (label, entry) = &dictionary[0];
+        assert_eq!(label, "sig1");
+        let ListEntry::InnerList(items, params) = entry else {
+            panic!("expected inner list");
+        };
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[1].bare_item.as_str(), Some("@target-uri"));
+        assert_eq!(
+            get_param(params, "created").and_then(BareItem::as_integer),
+            Some(1618884473)
+        );
+        // serializing again gives the identical string
+        assert_eq!(
+            format!("sig1={}", serialize_inner_list(items, params)),
+            input
+        );
+    }
+
+    #[test]
+    fn test_parse_byte_sequences() {
+        let dictionary =
+            parse_dictionary("sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:, unixtime=5")
+                .unwrap();
+        assert_eq!(dictionary.len(), 2);
+        let ListEntry::Item(item) = &dictionary[0].1 else {
+            panic!("expected item");
+        };
+        assert_eq!(item.bare_item.as_byte_sequence().map(<[u8]>::len), Some(32));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse_dictionary("sig1=(\"@method\"").is_err());
+        assert!(parse_dictionary("sig1=1.5").is_err());
+        assert!(parse_dictionary("Sig1=1").is_err());
+        assert!(parse_dictionary("sig1=1,").is_err());
+    }
+}
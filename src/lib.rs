@@ -21,9 +21,16 @@ pub mod fetch;
 pub mod http_signatures;
 pub mod protocol;
 pub(crate) mod reqwest_shim;
+#[cfg(feature = "ssrf")]
+pub mod ssrf;
 pub mod traits;
 
 pub use activitystreams_kinds as kinds;
 
 /// Mime type for Activitypub data, used for `Accept` and `Content-Type` HTTP headers
 pub static FEDERATION_CONTENT_TYPE: &str = "application/activity+json";
+
+/// `Accept` header for fetching Activitypub data. Prefers [FEDERATION_CONTENT_TYPE], but also
+/// accepts the JSON-LD media type with Activitystreams profile which some servers require.
+pub static FEDERATION_ACCEPT_HEADER: &str = "application/activity+json, \
+    application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"; q=0.9";
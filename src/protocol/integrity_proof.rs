@@ -0,0 +1,432 @@
+//! Object integrity proofs with the `eddsa-jcs-2022` cryptosuite
+//!
+//! HTTP signatures only prove which server delivered an activity. An integrity proof is embedded
+//! in the object itself and signed with the author's Ed25519 key, so the object can be verified no
+//! matter who delivered it, and without fetching it again from its origin.
+//!
+//! Outgoing activities are signed automatically if [Actor::ed25519_private_key_pem] returns a
+//! key, and proofs of incoming activities are verified in `receive_activity`. For embedded
+//! objects use [verify_integrity_proof].
+//!
+//! <https://codeberg.org/fediverse/fep/src/branch/main/fep/8b32/fep-8b32.md>
+
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::object_id::ObjectId,
+    traits::{Actor, Object},
+};
+use anyhow::anyhow;
+use chrono::{SecondsFormat, Utc};
+use openssl::{
+    pkey::{Id, PKey},
+    sign::{Signer, Verifier},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use url::Url;
+
+const PROOF_TYPE: &str = "DataIntegrityProof";
+const CRYPTOSUITE: &str = "eddsa-jcs-2022";
+const PROOF_PURPOSE: &str = "assertionMethod";
+/// Multicodec prefix of Ed25519 public keys
+const ED25519_PUB_PREFIX: [u8; 2] = [0xed, 0x01];
+
+/// Ed25519 public key of actors which is used to verify integrity proofs.
+///
+/// This needs to be federated in the `assertionMethod` field of actors which sign their
+/// activities.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Multikey {
+    /// Id of this public key
+    pub id: Url,
+    /// Always `Multikey`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// ID of the actor that this public key belongs to
+    pub controller: Url,
+    /// The public key in multibase format
+    pub public_key_multibase: String,
+}
+
+impl Multikey {
+    /// Create a new [Multikey] struct for the `controller` with the Ed25519 `public_key_pem`.
+    ///
+    /// It uses a standard key id of `{actor_id}#ed25519-key`
+    pub fn new(controller: Url, public_key_pem: &str) -> Result<Self, Error> {
+        let public_key =
+            PKey::public_key_from_pem(public_key_pem.as_bytes()).map_err(Error::other)?;
+        let raw = public_key.raw_public_key().map_err(Error::other)?;
+        let multicodec = [ED25519_PUB_PREFIX.as_slice(), &raw].concat();
+        Ok(Multikey {
+            id: ed25519_key_id(&controller),
+            kind: "Multikey".to_string(),
+            controller,
+            public_key_multibase: format!("z{}", bs58::encode(multicodec).into_string()),
+        })
+    }
+
+    /// Returns the public key in PEM format, for use in [Actor::ed25519_public_key_pem]
+    pub fn public_key_pem(&self) -> Result<String, Error> {
+        let invalid = || Error::Other(anyhow!("Invalid Ed25519 multikey"));
+        let multicodec = self
+            .public_key_multibase
+            .strip_prefix('z')
+            .and_then(|key| bs58::decode(key).into_vec().ok())
+            .ok_or_else(invalid)?;
+        let raw = multicodec
+            .strip_prefix(ED25519_PUB_PREFIX.as_slice())
+            .ok_or_else(invalid)?;
+        let public_key = PKey::public_key_from_raw_bytes(raw, Id::ED25519).map_err(Error::other)?;
+        let pem = public_key.public_key_to_pem().map_err(Error::other)?;
+        String::from_utf8(pem).map_err(Error::other)
+    }
+}
+
+/// A private/public Ed25519 key pair in PEM format, used for integrity proofs
+#[derive(Debug, Clone)]
+pub struct Ed25519Keypair {
+    /// Private key in PEM format
+    pub private_key: String,
+    /// Public key in PEM format
+    pub public_key: String,
+}
+
+/// Generate a random Ed25519 keypair for integrity proofs.
+pub fn generate_ed25519_keypair() -> Result<Ed25519Keypair, std::io::Error> {
+    let pkey = PKey::generate_ed25519()?;
+    let key_to_string = |key| String::from_utf8(key).map_err(std::io::Error::other);
+    Ok(Ed25519Keypair {
+        private_key: key_to_string(pkey.private_key_to_pem_pkcs8()?)?,
+        public_key: key_to_string(pkey.public_key_to_pem()?)?,
+    })
+}
+
+pub(crate) fn ed25519_key_id(controller: &Url) -> Url {
+    let mut key_id = controller.clone();
+    key_id.set_fragment(Some("ed25519-key"));
+    key_id
+}
+
+/// Adds an integrity proof to the JSON object, signed with the Ed25519 `private_key_pem` of
+/// `actor_id`.
+pub(crate) fn add_proof(
+    json: &mut Value,
+    actor_id: &Url,
+    private_key_pem: &str,
+) -> Result<(), Error> {
+    let mut proof = Map::new();
+    proof.insert("type".to_string(), PROOF_TYPE.into());
+    proof.insert("cryptosuite".to_string(), CRYPTOSUITE.into());
+    proof.insert(
+        "verificationMethod".to_string(),
+        ed25519_key_id(actor_id).to_string().into(),
+    );
+    proof.insert("proofPurpose".to_string(), PROOF_PURPOSE.into());
+    proof.insert(
+        "created".to_string(),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true).into(),
+    );
+
+    let hash_data = hash_data(json, &proof)?;
+    let private_key =
+        PKey::private_key_from_pem(private_key_pem.as_bytes()).map_err(Error::other)?;
+    let signature = Signer::new_without_digest(&private_key)
+        .and_then(|mut signer| signer.sign_oneshot_to_vec(&hash_data))
+        .map_err(Error::other)?;
+    proof.insert(
+        "proofValue".to_string(),
+        format!("z{}", bs58::encode(signature).into_string()).into(),
+    );
+
+    json.as_object_mut()
+        .ok_or_else(|| Error::Other(anyhow!("Only JSON objects can be signed")))?
+        .insert("proof".to_string(), proof.into());
+    Ok(())
+}
+
+/// Verifies the integrity proof of a JSON object, which must be signed by `actor`.
+///
+/// The `verificationMethod` of the proof must be the Ed25519 key id of the actor,
+/// `{actor_id}#ed25519-key`. Returns [Error::ActivitySignatureInvalid] if the object has no
+/// proof, if it was created with another key, if the actor has no Ed25519 key, or if the proof
+/// doesn't match the object.
+pub fn verify_proof<A: Actor>(json: &Value, actor: &A) -> Result<(), Error> {
+    let proof = json
+        .get("proof")
+        .and_then(Value::as_object)
+        .ok_or(Error::ActivitySignatureInvalid)?;
+    if proof.get("type").and_then(Value::as_str) != Some(PROOF_TYPE)
+        || proof.get("cryptosuite").and_then(Value::as_str) != Some(CRYPTOSUITE)
+        || proof.get("proofPurpose").and_then(Value::as_str) != Some(PROOF_PURPOSE)
+    {
+        return Err(Error::ActivitySignatureInvalid);
+    }
+    let verification_method = proof
+        .get("verificationMethod")
+        .and_then(Value::as_str)
+        .and_then(|v| Url::parse(v).ok())
+        .ok_or(Error::ActivitySignatureInvalid)?;
+    let key_id = ed25519_key_id(&actor.id());
+    if verification_method != key_id {
+        debug!("Integrity proof was created with key {verification_method}, expected {key_id}");
+        return Err(Error::ActivitySignatureInvalid);
+    }
+    let signature = proof
+        .get("proofValue")
+        .and_then(Value::as_str)
+        .and_then(|p| p.strip_prefix('z'))
+        .and_then(|p| bs58::decode(p).into_vec().ok())
+        .ok_or(Error::ActivitySignatureInvalid)?;
+    let public_key_pem = actor
+        .ed25519_public_key_pem()
+        .ok_or(Error::ActivitySignatureInvalid)?;
+
+    let mut proof_config = proof.clone();
+    proof_config.remove("proofValue");
+    let hash_data = hash_data(json, &proof_config)?;
+    let public_key = PKey::public_key_from_pem(public_key_pem.as_bytes()).map_err(Error::other)?;
+    let verified = Verifier::new_without_digest(&public_key)
+        .and_then(|mut verifier| verifier.verify_oneshot(&signature, &hash_data))
+        .map_err(Error::other)?;
+    if verified {
+        debug!("verified integrity proof from {}", actor.id());
+        Ok(())
+    } else {
+        Err(Error::ActivitySignatureInvalid)
+    }
+}
+
+/// Verifies the integrity proof of an object which was received embedded in another object, and
+/// returns the actor which created the proof.
+///
+/// The actor is taken from the `verificationMethod` of the proof, and must be on the same domain
+/// as the object id. If this succeeds, the object doesn't need to be fetched from its origin.
+pub async fn verify_integrity_proof<A>(
+    json: &Value,
+    data: &Data<<A as Object>::DataType>,
+) -> Result<A, <A as Object>::Error>
+where
+    A: Object + Actor,
+    <A as Object>::Error: From<Error> + From<anyhow::Error>,
+    for<'de2> <A as Object>::Kind: serde::Deserialize<'de2>,
+{
+    let controller = proof_controller(json).ok_or(Error::ActivitySignatureInvalid)?;
+    let object_id = json
+        .get("id")
+        .and_then(Value::as_str)
+        .and_then(|id| Url::parse(id).ok())
+        .ok_or(Error::ActivitySignatureInvalid)?;
+    if object_id.origin() != controller.origin() {
+        return Err(Error::UrlVerificationError(anyhow!(
+            "Integrity proof is from a different origin than the object"
+        ))
+        .into());
+    }
+    let actor = ObjectId::<A>::from(controller).dereference(data).await?;
+    verify_proof(json, &actor)?;
+    Ok(actor)
+}
+
+/// Returns the actor id from the `verificationMethod` of the proof
+fn proof_controller(json: &Value) -> Option<Url> {
+    let mut controller = json
+        .get("proof")?
+        .get("verificationMethod")
+        .and_then(Value::as_str)
+        .and_then(|v| Url::parse(v).ok())?;
+    controller.set_fragment(None);
+    Some(controller)
+}
+
+/// Returns the data which is signed, consisting of the hashes of the canonicalized proof
+/// configuration and of the canonicalized object without proof.
+fn hash_data(json: &Value, proof_config: &Map<String, Value>) -> Result<Vec<u8>, Error> {
+    let mut document = json
+        .as_object()
+        .ok_or(Error::ActivitySignatureInvalid)?
+        .clone();
+    document.remove("proof");
+    let mut proof_config = proof_config.clone();
+    if let Some(context) = document.get("@context") {
+        proof_config.insert("@context".to_string(), context.clone());
+    }
+    let canonical_proof = serde_jcs::to_vec(&proof_config).map_err(Error::other)?;
+    let canonical_document = serde_jcs::to_vec(&document).map_err(Error::other)?;
+    Ok([
+        Sha256::digest(canonical_proof),
+        Sha256::digest(canonical_document),
+    ]
+    .concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        traits::tests::{DbConnection, DbUser, DB_USER, DB_USER_ED25519_KEYPAIR},
+    };
+    use serde_json::json;
+
+    fn signed_note() -> Value {
+        let mut note = json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": "https://localhost/123/note/1",
+            "type": "Note",
+            "attributedTo": "https://localhost/123",
+            "content": "Hello world",
+        });
+        add_proof(
+            &mut note,
+            &DB_USER.federation_id,
+            &DB_USER_ED25519_KEYPAIR.private_key,
+        )
+        .unwrap();
+        note
+    }
+
+    #[test]
+    fn test_verify_proof() {
+        let note = signed_note();
+        assert!(verify_proof(&note, &*DB_USER).is_ok());
+
+        // key order and whitespace don't matter
+        let reordered: Value = serde_json::from_str(&format!(
+            "{{\"proof\": {}, \"content\": \"Hello world\", \"type\": \"Note\", \"id\": {}, \
+             \"attributedTo\": {}, \"@context\": {}}}",
+            note["proof"], note["id"], note["attributedTo"], note["@context"]
+        ))
+        .unwrap();
+        assert!(verify_proof(&reordered, &*DB_USER).is_ok());
+    }
+
+    #[test]
+    fn test_verify_proof_invalid() {
+        let mut note = signed_note();
+        note["content"] = "Goodbye world".into();
+        assert_eq!(
+            verify_proof(&note, &*DB_USER),
+            Err(Error::ActivitySignatureInvalid)
+        );
+
+        let mut note = signed_note();
+        note["proof"]["created"] = "2000-01-01T00:00:00Z".into();
+        assert_eq!(
+            verify_proof(&note, &*DB_USER),
+            Err(Error::ActivitySignatureInvalid)
+        );
+
+        let mut note = signed_note();
+        note["proof"]["verificationMethod"] = "https://example.com/456#ed25519-key".into();
+        assert_eq!(
+            verify_proof(&note, &*DB_USER),
+            Err(Error::ActivitySignatureInvalid)
+        );
+
+        // same actor, but not its Ed25519 key
+        for key_id in ["https://localhost/123#main-key", "https://localhost/123"] {
+            let mut note = signed_note();
+            note["proof"]["verificationMethod"] = key_id.into();
+            assert_eq!(
+                verify_proof(&note, &*DB_USER),
+                Err(Error::ActivitySignatureInvalid)
+            );
+        }
+    }
+
+    /// Test vector from FEP-8b32
+    #[test]
+    fn test_verify_proof_fep_8b32() {
+        let activity = json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                "https://w3id.org/security/data-integrity/v1"
+            ],
+            "id": "https://server.example/activities/1",
+            "type": "Create",
+            "actor": "https://server.example/users/alice",
+            "object": {
+                "id": "https://server.example/objects/1",
+                "type": "Note",
+                "attributedTo": "https://server.example/users/alice",
+                "content": "Hello world",
+                "location": {
+                    "type": "Place",
+                    "longitude": -71.184902,
+                    "latitude": 25.273962
+                }
+            },
+            "proof": {
+                "@context": [
+                    "https://www.w3.org/ns/activitystreams",
+                    "https://w3id.org/security/data-integrity/v1"
+                ],
+                "type": "DataIntegrityProof",
+                "cryptosuite": "eddsa-jcs-2022",
+                "verificationMethod": "https://server.example/users/alice#ed25519-key",
+                "proofPurpose": "assertionMethod",
+                "proofValue": "zLaewdp4H9kqtwyrLatK4cjY5oRHwVcw4gibPSUDYDMhi4M49v8pcYk3ZB6D69dNpAPbUmY8ocuJ3m9KhKJEEg7z",
+                "created": "2023-02-24T23:36:38Z"
+            }
+        });
+        let controller: Url = "https://server.example/users/alice".parse().unwrap();
+        let multikey = Multikey {
+            id: ed25519_key_id(&controller),
+            kind: "Multikey".to_string(),
+            controller: controller.clone(),
+            public_key_multibase: "z6MkrJVnaZkeFzdQyMZu1cgjg7k1pZZ6pvBQ7XJPt4swbTQ2".to_string(),
+        };
+        let mut alice = DB_USER.clone();
+        alice.federation_id = controller;
+        alice.ed25519_public_key = Some(multikey.public_key_pem().unwrap());
+        assert!(verify_proof(&activity, &alice).is_ok());
+
+        let mut modified = activity.clone();
+        modified["object"]["content"] = "Goodbye world".into();
+        assert_eq!(
+            verify_proof(&modified, &alice),
+            Err(Error::ActivitySignatureInvalid)
+        );
+    }
+
+    #[test]
+    fn test_multikey() {
+        let multikey = Multikey::new(
+            DB_USER.federation_id.clone(),
+            &DB_USER_ED25519_KEYPAIR.public_key,
+        )
+        .unwrap();
+        assert_eq!(multikey.id.as_str(), "https://localhost/123#ed25519-key");
+        assert!(multikey.public_key_multibase.starts_with("z6Mk"));
+        assert_eq!(
+            multikey.public_key_pem().unwrap(),
+            DB_USER_ED25519_KEYPAIR.public_key
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_integrity_proof() -> Result<(), anyhow::Error> {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .await?;
+        let data = config.to_request_data();
+
+        let note = signed_note();
+        let actor = verify_integrity_proof::<DbUser>(&note, &data).await?;
+        assert_eq!(actor.federation_id, DB_USER.federation_id);
+
+        let mut note = signed_note();
+        note["id"] = "https://example.net/note/1".into();
+        assert!(verify_integrity_proof::<DbUser>(&note, &data)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,881 @@
+//! Minimal JSON-LD processor for canonicalizing signed documents
+//!
+//! Implements the parts of [JSON-LD 1.0](https://www.w3.org/TR/2014/REC-json-ld-api-20140116/)
+//! expansion and RDF conversion which are needed to verify Linked Data Signatures, followed by
+//! [URDNA2015](https://www.w3.org/TR/rdf-canon/) canonicalization. Remote contexts are never
+//! fetched. Only the documents in `contexts/` can be referenced, any other context URL results in
+//! an error.
+
+use anyhow::{anyhow, bail};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap};
+
+mod urdna2015;
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDF_FIRST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#first";
+const RDF_REST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#rest";
+const RDF_NIL: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#nil";
+const RDF_LANG_STRING: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#langString";
+const XSD_STRING: &str = "http://www.w3.org/2001/XMLSchema#string";
+const XSD_BOOLEAN: &str = "http://www.w3.org/2001/XMLSchema#boolean";
+const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+const XSD_DOUBLE: &str = "http://www.w3.org/2001/XMLSchema#double";
+
+/// Context documents which are available without network access
+const CONTEXT_DOCUMENTS: [(&str, &str); 3] = [
+    (
+        "https://www.w3.org/ns/activitystreams",
+        include_str!("contexts/activitystreams.jsonld"),
+    ),
+    (
+        "https://w3id.org/security/v1",
+        include_str!("contexts/security-v1.jsonld"),
+    ),
+    (
+        "https://w3id.org/identity/v1",
+        include_str!("contexts/identity-v1.jsonld"),
+    ),
+];
+
+/// Converts a JSON-LD document to canonical N-Quads, using the URDNA2015 algorithm.
+pub(crate) fn canonicalize(document: &Value) -> Result<String, anyhow::Error> {
+    let expanded = expand(document)?;
+    let quads = to_rdf(&expanded)?;
+    Ok(urdna2015::canonicalize(quads))
+}
+
+#[derive(Clone, Debug, Default)]
+struct Context {
+    /// Term definitions, `None` if the term was explicitly mapped to null
+    terms: HashMap<String, Option<TermDefinition>>,
+    vocab: Option<String>,
+    language: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct TermDefinition {
+    id: String,
+    type_mapping: Option<String>,
+    container: Option<String>,
+    /// `Some(None)` if the language was explicitly set to null for this term
+    language: Option<Option<String>>,
+}
+
+impl Context {
+    fn term(&self, term: &str) -> Option<&TermDefinition> {
+        self.terms.get(term).and_then(Option::as_ref)
+    }
+
+    fn container(&self, term: Option<&str>) -> Option<&str> {
+        term.and_then(|t| self.term(t))
+            .and_then(|d| d.container.as_deref())
+    }
+
+    fn process(&self, local_context: &Value, depth: usize) -> Result<Context, anyhow::Error> {
+        if depth > 10 {
+            bail!("Too many nested JSON-LD contexts");
+        }
+        let mut result = self.clone();
+        let contexts = match local_context {
+            Value::Array(a) => a.clone(),
+            c => vec![c.clone()],
+        };
+        for context in contexts {
+            match context {
+                Value::Null => result = Context::default(),
+                Value::String(url) => {
+                    let document = CONTEXT_DOCUMENTS
+                        .iter()
+                        .find(|(u, _)| u == &url.trim_end_matches('/'))
+                        .map(|(_, d)| *d)
+                        .ok_or_else(|| anyhow!("Unknown JSON-LD context {url}"))?;
+                    let document: Value = serde_json::from_str(document)?;
+                    let context = document
+                        .get("@context")
+                        .ok_or_else(|| anyhow!("Context document {url} has no @context"))?;
+                    result = result.process(context, depth + 1)?;
+                }
+                Value::Object(local) => {
+                    if let Some(vocab) = local.get("@vocab") {
+                        result.vocab = match vocab {
+                            Value::Null => None,
+                            Value::String(v) if v.contains(':') => Some(v.clone()),
+                            _ => bail!("Invalid @vocab mapping"),
+                        };
+                    }
+                    if let Some(language) = local.get("@language") {
+                        result.language = match language {
+                            Value::Null => None,
+                            Value::String(l) => Some(l.to_lowercase()),
+                            _ => bail!("Invalid default language"),
+                        };
+                    }
+                    let mut defined = HashMap::new();
+                    for term in local.keys() {
+                        if !["@base", "@vocab", "@language", "@version"].contains(&term.as_str()) {
+                            result.create_term_definition(&local, term, &mut defined)?;
+                        }
+                    }
+                }
+                _ => bail!("Invalid local context"),
+            }
+        }
+        Ok(result)
+    }
+
+    fn create_term_definition(
+        &mut self,
+        local: &Map<String, Value>,
+        term: &str,
+        defined: &mut HashMap<String, bool>,
+    ) -> Result<(), anyhow::Error> {
+        match defined.get(term) {
+            Some(true) => return Ok(()),
+            Some(false) => bail!("Cyclic IRI mapping for term {term}"),
+            None => {}
+        }
+        defined.insert(term.to_string(), false);
+        if is_keyword(term) {
+            bail!("Keyword {term} can not be redefined");
+        }
+        self.terms.remove(term);
+
+        let value = match local.get(term) {
+            None | Some(Value::Null) => {
+                self.terms.insert(term.to_string(), None);
+                defined.insert(term.to_string(), true);
+                return Ok(());
+            }
+            Some(Value::String(id)) => {
+                let mut map = Map::new();
+                map.insert("@id".to_string(), Value::String(id.clone()));
+                map
+            }
+            Some(Value::Object(o)) => o.clone(),
+            Some(_) => bail!("Invalid term definition for {term}"),
+        };
+
+        let mut definition = TermDefinition::default();
+        if let Some(type_mapping) = value.get("@type") {
+            let type_mapping = type_mapping
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid type mapping for {term}"))?;
+            let type_mapping = self
+                .expand_iri_defining(type_mapping, true, local, defined)?
+                .ok_or_else(|| anyhow!("Invalid type mapping for {term}"))?;
+            if !["@id", "@vocab"].contains(&type_mapping.as_str()) && !type_mapping.contains(':') {
+                bail!("Invalid type mapping for {term}");
+            }
+            definition.type_mapping = Some(type_mapping);
+        }
+        if value.contains_key("@reverse") {
+            bail!("Reverse properties are not supported");
+        }
+        match value.get("@id") {
+            Some(Value::String(id)) if id != term => {
+                definition.id = self
+                    .expand_iri_defining(id, true, local, defined)?
+                    .ok_or_else(|| anyhow!("Invalid IRI mapping for {term}"))?;
+                if !is_keyword(&definition.id) && !definition.id.contains(':') {
+                    bail!("Invalid IRI mapping for {term}");
+                }
+            }
+            Some(Value::String(_)) | None => {
+                if let Some((prefix, suffix)) = term.split_once(':') {
+                    if local.contains_key(prefix) {
+                        self.create_term_definition(local, prefix, defined)?;
+                    }
+                    definition.id = match self.term(prefix) {
+                        Some(p) => format!("{}{suffix}", p.id),
+                        None => term.to_string(),
+                    };
+                } else if let Some(vocab) = &self.vocab {
+                    definition.id = format!("{vocab}{term}");
+                } else {
+                    bail!("Invalid IRI mapping for {term}");
+                }
+            }
+            Some(_) => bail!("Invalid IRI mapping for {term}"),
+        }
+        if let Some(container) = value.get("@container") {
+            match container.as_str() {
+                Some(c @ ("@list" | "@set" | "@index" | "@language")) => {
+                    definition.container = Some(c.to_string())
+                }
+                _ => bail!("Unsupported container mapping for {term}"),
+            }
+        }
+        if let Some(language) = value.get("@language") {
+            definition.language = Some(match language {
+                Value::Null => None,
+                Value::String(l) => Some(l.to_lowercase()),
+                _ => bail!("Invalid language mapping for {term}"),
+            });
+        }
+
+        self.terms.insert(term.to_string(), Some(definition));
+        defined.insert(term.to_string(), true);
+        Ok(())
+    }
+
+    /// IRI expansion while processing a local context, which may define terms on the way.
+    fn expand_iri_defining(
+        &mut self,
+        value: &str,
+        vocab: bool,
+        local: &Map<String, Value>,
+        defined: &mut HashMap<String, bool>,
+    ) -> Result<Option<String>, anyhow::Error> {
+        if local.contains_key(value) && defined.get(value) != Some(&true) {
+            self.create_term_definition(local, value, defined)?;
+        }
+        if let Some((prefix, _)) = value.split_once(':') {
+            if local.contains_key(prefix) && defined.get(prefix) != Some(&true) {
+                self.create_term_definition(local, prefix, defined)?;
+            }
+        }
+        Ok(self.expand_iri(value, vocab))
+    }
+
+    /// Expands a term, compact IRI or IRI. Returns `None` if the value is mapped to null.
+    fn expand_iri(&self, value: &str, vocab: bool) -> Option<String> {
+        if is_keyword(value) {
+            return Some(value.to_string());
+        }
+        if vocab {
+            if let Some(definition) = self.terms.get(value) {
+                return definition.as_ref().map(|d| d.id.clone());
+            }
+        }
+        if let Some((prefix, suffix)) = value.split_once(':') {
+            if prefix == "_" || suffix.starts_with("//") {
+                return Some(value.to_string());
+            }
+            if let Some(definition) = self.term(prefix) {
+                return Some(format!("{}{suffix}", definition.id));
+            }
+            return Some(value.to_string());
+        }
+        if vocab {
+            if let Some(vocab) = &self.vocab {
+                return Some(format!("{vocab}{value}"));
+            }
+        }
+        // no base IRI is set, so relative IRIs are left unchanged
+        Some(value.to_string())
+    }
+}
+
+fn is_keyword(value: &str) -> bool {
+    [
+        "@context",
+        "@id",
+        "@value",
+        "@language",
+        "@type",
+        "@container",
+        "@list",
+        "@set",
+        "@reverse",
+        "@index",
+        "@base",
+        "@vocab",
+        "@graph",
+    ]
+    .contains(&value)
+}
+
+fn is_absolute_iri(value: &str) -> bool {
+    match value.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        }
+        None => false,
+    }
+}
+
+fn as_array(value: Value) -> Vec<Value> {
+    match value {
+        Value::Array(a) => a,
+        Value::Null => vec![],
+        v => vec![v],
+    }
+}
+
+/// Expansion algorithm, returns the expanded document as array of node objects.
+fn expand(document: &Value) -> Result<Vec<Value>, anyhow::Error> {
+    let expanded = expand_element(&Context::default(), None, document)?;
+    let expanded = match expanded {
+        Value::Object(mut o) if o.len() == 1 && o.contains_key("@graph") => {
+            o.remove("@graph").unwrap_or_default()
+        }
+        e => e,
+    };
+    Ok(as_array(expanded))
+}
+
+fn expand_element(
+    active: &Context,
+    active_property: Option<&str>,
+    element: &Value,
+) -> Result<Value, anyhow::Error> {
+    match element {
+        Value::Null => Ok(Value::Null),
+        Value::Array(items) => {
+            let mut result = vec![];
+            for item in items {
+                let expanded = expand_element(active, active_property, item)?;
+                if active.container(active_property) == Some("@list")
+                    && (expanded.is_array() || expanded.get("@list").is_some())
+                {
+                    bail!("Lists of lists are not supported");
+                }
+                match expanded {
+                    Value::Array(a) => result.extend(a),
+                    Value::Null => {}
+                    e => result.push(e),
+                }
+            }
+            Ok(Value::Array(result))
+        }
+        Value::Object(object) => expand_object(active, active_property, object),
+        scalar => match active_property {
+            None | Some("@graph") => Ok(Value::Null),
+            Some(property) => Ok(expand_value(active, property, scalar)),
+        },
+    }
+}
+
+fn expand_object(
+    active: &Context,
+    active_property: Option<&str>,
+    object: &Map<String, Value>,
+) -> Result<Value, anyhow::Error> {
+    let context;
+    let active = match object.get("@context") {
+        Some(local) => {
+            context = active.process(local, 0)?;
+            &context
+        }
+        None => active,
+    };
+
+    let mut result = Map::new();
+    let mut keys: Vec<&String> = object.keys().collect();
+    keys.sort();
+    for key in keys {
+        let value = &object[key];
+        if key == "@context" {
+            continue;
+        }
+        let Some(property) = active.expand_iri(key, true) else {
+            continue;
+        };
+        if !property.contains(':') && !is_keyword(&property) {
+            continue;
+        }
+
+        if is_keyword(&property) {
+            if result.contains_key(&property) {
+                bail!("Colliding keywords {property}");
+            }
+            let expanded = match property.as_str() {
+                "@id" => {
+                    let id = value.as_str().ok_or_else(|| anyhow!("Invalid @id value"))?;
+                    Value::String(active.expand_iri(id, false).unwrap_or_default())
+                }
+                "@type" => {
+                    let types: Result<Vec<_>, _> = as_array(value.clone())
+                        .iter()
+                        .map(|t| {
+                            let t = t.as_str().ok_or_else(|| anyhow!("Invalid @type value"))?;
+                            Ok::<_, anyhow::Error>(Value::String(
+                                active.expand_iri(t, true).unwrap_or_default(),
+                            ))
+                        })
+                        .collect();
+                    match value {
+                        Value::Array(_) => Value::Array(types?),
+                        _ => types?.pop().unwrap_or_default(),
+                    }
+                }
+                "@graph" => expand_element(active, Some("@graph"), value)?,
+                "@value" => {
+                    if value.is_array() || value.is_object() {
+                        bail!("Invalid @value");
+                    }
+                    result.insert(property, value.clone());
+                    continue;
+                }
+                "@language" => Value::String(
+                    value
+                        .as_str()
+                        .ok_or_else(|| anyhow!("Invalid @language value"))?
+                        .to_lowercase(),
+                ),
+                "@index" => {
+                    if !value.is_string() {
+                        bail!("Invalid @index value");
+                    }
+                    value.clone()
+                }
+                "@list" => {
+                    if matches!(active_property, None | Some("@graph")) {
+                        continue;
+                    }
+                    let expanded = expand_element(active, active_property, value)?;
+                    if as_array(expanded.clone())
+                        .iter()
+                        .any(|e| e.get("@list").is_some())
+                    {
+                        bail!("Lists of lists are not supported");
+                    }
+                    Value::Array(as_array(expanded))
+                }
+                "@set" => expand_element(active, active_property, value)?,
+                "@reverse" => bail!("Reverse properties are not supported"),
+                _ => continue,
+            };
+            if !expanded.is_null() {
+                result.insert(property, expanded);
+            }
+            continue;
+        }
+
+        let definition = active.term(key);
+        let container = definition.and_then(|d| d.container.as_deref());
+        let expanded = match (container, value) {
+            (Some("@language"), Value::Object(languages)) => {
+                let mut values = vec![];
+                let mut languages: Vec<_> = languages.iter().collect();
+                languages.sort_by_key(|(l, _)| *l);
+                for (language, items) in languages {
+                    for item in as_array(items.clone()) {
+                        let item = item
+                            .as_str()
+                            .ok_or_else(|| anyhow!("Invalid language map value"))?;
+                        let mut value = Map::new();
+                        value.insert("@value".to_string(), Value::String(item.to_string()));
+                        value.insert(
+                            "@language".to_string(),
+                            Value::String(language.to_lowercase()),
+                        );
+                        values.push(Value::Object(value));
+                    }
+                }
+                Value::Array(values)
+            }
+            (Some("@index"), Value::Object(indexes)) => {
+                let mut values = vec![];
+                let mut indexes: Vec<_> = indexes.iter().collect();
+                indexes.sort_by_key(|(i, _)| *i);
+                for (index, items) in indexes {
+                    for mut item in as_array(expand_element(active, Some(key), items)?) {
+                        if let Value::Object(o) = &mut item {
+                            o.entry("@index")
+                                .or_insert_with(|| Value::String(index.clone()));
+                        }
+                        values.push(item);
+                    }
+                }
+                Value::Array(values)
+            }
+            _ => expand_element(active, Some(key), value)?,
+        };
+        if expanded.is_null() {
+            continue;
+        }
+        let expanded = if container == Some("@list") && expanded.get("@list").is_none() {
+            let mut list = Map::new();
+            list.insert("@list".to_string(), Value::Array(as_array(expanded)));
+            Value::Object(list)
+        } else {
+            expanded
+        };
+        let entry = result
+            .entry(property)
+            .or_insert_with(|| Value::Array(vec![]));
+        if let Value::Array(a) = entry {
+            match expanded {
+                Value::Array(e) => a.extend(e),
+                e => a.push(e),
+            }
+        }
+    }
+
+    if result.contains_key("@value") {
+        if result
+            .keys()
+            .any(|k| !["@value", "@language", "@type", "@index"].contains(&k.as_str()))
+            || (result.contains_key("@language") && result.contains_key("@type"))
+        {
+            bail!("Invalid value object");
+        }
+        if result["@value"].is_null() {
+            return Ok(Value::Null);
+        }
+        if !result["@value"].is_string() && result.contains_key("@language") {
+            bail!("Invalid language-tagged value");
+        }
+        if result.get("@type").is_some_and(|t| !t.is_string()) {
+            bail!("Invalid typed value");
+        }
+    } else if let Some(types) = result.get_mut("@type") {
+        if !types.is_array() {
+            *types = Value::Array(vec![types.take()]);
+        }
+    } else if result.contains_key("@set") || result.contains_key("@list") {
+        if result.len() > 2 || (result.len() == 2 && !result.contains_key("@index")) {
+            bail!("Invalid set or list object");
+        }
+        if let Some(set) = result.remove("@set") {
+            return Ok(set);
+        }
+    }
+    if result.len() == 1 && result.contains_key("@language") {
+        return Ok(Value::Null);
+    }
+
+    if matches!(active_property, None | Some("@graph"))
+        && (result.is_empty()
+            || result.contains_key("@value")
+            || result.contains_key("@list")
+            || (result.len() == 1 && result.contains_key("@id")))
+    {
+        return Ok(Value::Null);
+    }
+    Ok(Value::Object(result))
+}
+
+fn expand_value(active: &Context, active_property: &str, value: &Value) -> Value {
+    let definition = active.term(active_property);
+    let type_mapping = definition.and_then(|d| d.type_mapping.as_deref());
+    let mut result = Map::new();
+    match (type_mapping, value) {
+        (Some("@id"), Value::String(s)) => {
+            result.insert(
+                "@id".to_string(),
+                Value::String(active.expand_iri(s, false).unwrap_or_default()),
+            );
+        }
+        (Some("@vocab"), Value::String(s)) => {
+            result.insert(
+                "@id".to_string(),
+                Value::String(active.expand_iri(s, true).unwrap_or_default()),
+            );
+        }
+        _ => {
+            result.insert("@value".to_string(), value.clone());
+            match type_mapping {
+                Some(t) if t != "@id" && t != "@vocab" => {
+                    result.insert("@type".to_string(), Value::String(t.to_string()));
+                }
+                _ if value.is_string() => {
+                    let language = match definition.and_then(|d| d.language.as_ref()) {
+                        Some(language) => language.clone(),
+                        None => active.language.clone(),
+                    };
+                    if let Some(language) = language {
+                        result.insert("@language".to_string(), Value::String(language));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Value::Object(result)
+}
+
+/// Node in an RDF quad
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Term {
+    Iri(String),
+    BlankNode(String),
+    Literal {
+        value: String,
+        datatype: String,
+        language: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Quad {
+    subject: Term,
+    predicate: Term,
+    object: Term,
+    graph: Option<Term>,
+}
+
+/// Generates blank node labels for nodes without `@id`, and relabels existing blank nodes.
+#[derive(Default)]
+struct BlankNodeGenerator {
+    counter: usize,
+    labels: HashMap<String, String>,
+}
+
+impl BlankNodeGenerator {
+    fn generate(&mut self, existing: Option<&str>) -> String {
+        if let Some(label) = existing.and_then(|e| self.labels.get(e)) {
+            return label.clone();
+        }
+        let label = format!("_:b{}", self.counter);
+        self.counter += 1;
+        if let Some(existing) = existing {
+            self.labels.insert(existing.to_string(), label.clone());
+        }
+        label
+    }
+}
+
+/// Converts expanded JSON-LD to an RDF dataset.
+fn to_rdf(expanded: &[Value]) -> Result<BTreeSet<Quad>, anyhow::Error> {
+    let mut quads = BTreeSet::new();
+    let mut generator = BlankNodeGenerator::default();
+    for node in expanded {
+        node_to_rdf(node, None, &mut generator, &mut quads)?;
+    }
+    Ok(quads)
+}
+
+/// Adds the quads of a node object and all nested nodes, and returns the node's subject.
+fn node_to_rdf(
+    node: &Value,
+    graph: Option<&Term>,
+    generator: &mut BlankNodeGenerator,
+    quads: &mut BTreeSet<Quad>,
+) -> Result<Option<Term>, anyhow::Error> {
+    let object = node
+        .as_object()
+        .ok_or_else(|| anyhow!("Expected node object"))?;
+    let subject = match object.get("@id").and_then(Value::as_str) {
+        Some(id) if id.starts_with("_:") => Some(Term::BlankNode(generator.generate(Some(id)))),
+        Some(id) if is_absolute_iri(id) => Some(Term::Iri(id.to_string())),
+        Some(_) => None,
+        None => Some(Term::BlankNode(generator.generate(None))),
+    };
+
+    let mut properties: Vec<_> = object.iter().collect();
+    properties.sort_by_key(|(p, _)| *p);
+    for (property, values) in properties {
+        match property.as_str() {
+            "@type" => {
+                for t in values.as_array().into_iter().flatten() {
+                    let Some(object) = t.as_str().and_then(|t| iri_or_blank_node(t, generator))
+                    else {
+                        continue;
+                    };
+                    if let Some(subject) = &subject {
+                        quads.insert(Quad {
+                            subject: subject.clone(),
+                            predicate: Term::Iri(RDF_TYPE.to_string()),
+                            object,
+                            graph: graph.cloned(),
+                        });
+                    }
+                }
+            }
+            "@graph" => {
+                let named_graph = match object.get("@id") {
+                    Some(_) => subject.clone(),
+                    None => graph.cloned(),
+                };
+                for node in values.as_array().into_iter().flatten() {
+                    node_to_rdf(node, named_graph.as_ref(), generator, quads)?;
+                }
+            }
+            p if is_keyword(p) => {}
+            // properties which are blank nodes or relative IRIs can't be represented in RDF
+            p if p.starts_with("_:") || !is_absolute_iri(p) => {}
+            p => {
+                for value in values.as_array().into_iter().flatten() {
+                    let object = object_to_rdf(value, graph, generator, quads)?;
+                    if let (Some(subject), Some(object)) = (&subject, object) {
+                        quads.insert(Quad {
+                            subject: subject.clone(),
+                            predicate: Term::Iri(p.to_string()),
+                            object,
+                            graph: graph.cloned(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    Ok(subject)
+}
+
+fn iri_or_blank_node(value: &str, generator: &mut BlankNodeGenerator) -> Option<Term> {
+    if value.starts_with("_:") {
+        Some(Term::BlankNode(generator.generate(Some(value))))
+    } else if is_absolute_iri(value) {
+        Some(Term::Iri(value.to_string()))
+    } else {
+        None
+    }
+}
+
+fn object_to_rdf(
+    value: &Value,
+    graph: Option<&Term>,
+    generator: &mut BlankNodeGenerator,
+    quads: &mut BTreeSet<Quad>,
+) -> Result<Option<Term>, anyhow::Error> {
+    if let Some(list) = value.get("@list") {
+        return list_to_rdf(list, graph, generator, quads);
+    }
+    let Some(literal) = value.get("@value") else {
+        return node_to_rdf(value, graph, generator, quads);
+    };
+    let datatype = value.get("@type").and_then(Value::as_str);
+    let language = value.get("@language").and_then(Value::as_str);
+    let (value, default_datatype) = match literal {
+        Value::Bool(b) => (b.to_string(), XSD_BOOLEAN),
+        Value::Number(n) if n.is_f64() || datatype == Some(XSD_DOUBLE) => {
+            let f = n.as_f64().unwrap_or_default();
+            if f.fract() != 0.0 || f.abs() >= 1e21 || datatype == Some(XSD_DOUBLE) {
+                (canonical_double(f), XSD_DOUBLE)
+            } else {
+                ((f as i64).to_string(), XSD_INTEGER)
+            }
+        }
+        Value::Number(n) => (n.to_string(), XSD_INTEGER),
+        Value::String(s) => (s.clone(), XSD_STRING),
+        _ => bail!("Invalid literal value"),
+    };
+    let datatype = match (language, datatype) {
+        (Some(_), _) => RDF_LANG_STRING.to_string(),
+        (None, Some(d)) => d.to_string(),
+        (None, None) => default_datatype.to_string(),
+    };
+    Ok(Some(Term::Literal {
+        value,
+        datatype,
+        language: language.map(str::to_string),
+    }))
+}
+
+fn list_to_rdf(
+    list: &Value,
+    graph: Option<&Term>,
+    generator: &mut BlankNodeGenerator,
+    quads: &mut BTreeSet<Quad>,
+) -> Result<Option<Term>, anyhow::Error> {
+    let items = list.as_array().cloned().unwrap_or_default();
+    if items.is_empty() {
+        return Ok(Some(Term::Iri(RDF_NIL.to_string())));
+    }
+    let nodes: Vec<Term> = items
+        .iter()
+        .map(|_| Term::BlankNode(generator.generate(None)))
+        .collect();
+    for (i, item) in items.iter().enumerate() {
+        if let Some(object) = object_to_rdf(item, graph, generator, quads)? {
+            quads.insert(Quad {
+                subject: nodes[i].clone(),
+                predicate: Term::Iri(RDF_FIRST.to_string()),
+                object,
+                graph: graph.cloned(),
+            });
+        }
+        let rest = nodes
+            .get(i + 1)
+            .cloned()
+            .unwrap_or_else(|| Term::Iri(RDF_NIL.to_string()));
+        quads.insert(Quad {
+            subject: nodes[i].clone(),
+            predicate: Term::Iri(RDF_REST.to_string()),
+            object: rest,
+            graph: graph.cloned(),
+        });
+    }
+    Ok(nodes.first().cloned())
+}
+
+/// Formats a double in the canonical lexical form of JSON-LD, such as `1.1E0`.
+fn canonical_double(value: f64) -> String {
+    let formatted = format!("{value:.15E}");
+    let (mantissa, exponent) = formatted.split_once('E').unwrap_or((&formatted, "0"));
+    let mantissa = match mantissa.split_once('.') {
+        Some((int, fraction)) => {
+            let fraction = fraction.trim_end_matches('0');
+            format!("{int}.{}", if fraction.is_empty() { "0" } else { fraction })
+        }
+        None => format!("{mantissa}.0"),
+    };
+    format!("{mantissa}E{exponent}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonicalize_note() {
+        let note = json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                {"sensitive": "as:sensitive", "toot": "http://joinmastodon.org/ns#"}
+            ],
+            "id": "https://example.com/notes/1",
+            "type": "Note",
+            "attributedTo": "https://example.com/users/alice",
+            "content": "Hello \"world\"",
+            "contentMap": {"en": "Hello \"world\""},
+            "sensitive": false,
+            "unknownProperty": "dropped",
+            "to": ["https://www.w3.org/ns/activitystreams#Public"],
+            "tag": [{"type": "Mention", "href": "https://example.com/users/bob"}]
+        });
+        let expected = r#"<https://example.com/notes/1> <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#Note> .
+<https://example.com/notes/1> <https://www.w3.org/ns/activitystreams#attributedTo> <https://example.com/users/alice> .
+<https://example.com/notes/1> <https://www.w3.org/ns/activitystreams#content> "Hello \"world\"" .
+<https://example.com/notes/1> <https://www.w3.org/ns/activitystreams#content> "Hello \"world\""@en .
+<https://example.com/notes/1> <https://www.w3.org/ns/activitystreams#sensitive> "false"^^<http://www.w3.org/2001/XMLSchema#boolean> .
+<https://example.com/notes/1> <https://www.w3.org/ns/activitystreams#tag> _:c14n0 .
+<https://example.com/notes/1> <https://www.w3.org/ns/activitystreams#to> <https://www.w3.org/ns/activitystreams#Public> .
+_:c14n0 <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#Mention> .
+_:c14n0 <https://www.w3.org/ns/activitystreams#href> <https://example.com/users/bob> .
+"#;
+        assert_eq!(canonicalize(&note).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_canonicalize_key_order() {
+        let a = json!({
+            "@context": "https://w3id.org/identity/v1",
+            "creator": "https://example.com/users/alice#main-key",
+            "created": "2024-01-01T00:00:00Z"
+        });
+        let b = json!({
+            "created": "2024-01-01T00:00:00Z",
+            "creator": "https://example.com/users/alice#main-key",
+            "@context": "https://w3id.org/identity/v1"
+        });
+        let expected = r#"_:c14n0 <http://purl.org/dc/terms/created> "2024-01-01T00:00:00Z"^^<http://www.w3.org/2001/XMLSchema#dateTime> .
+_:c14n0 <http://purl.org/dc/terms/creator> <https://example.com/users/alice#main-key> .
+"#;
+        assert_eq!(canonicalize(&a).unwrap(), expected);
+        assert_eq!(canonicalize(&b).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_canonicalize_unknown_context() {
+        let document = json!({
+            "@context": "https://example.com/context.jsonld",
+            "id": "https://example.com/notes/1"
+        });
+        assert!(canonicalize(&document).is_err());
+    }
+
+    #[test]
+    fn test_canonical_double() {
+        assert_eq!(canonical_double(1.1), "1.1E0");
+        assert_eq!(canonical_double(-0.5), "-5.0E-1");
+        assert_eq!(canonical_double(1e21), "1.0E21");
+    }
+}
@@ -0,0 +1,485 @@
+//! RDF dataset canonicalization with the URDNA2015 algorithm
+//!
+//! <https://www.w3.org/TR/rdf-canon/>
+
+use super::{Quad, Term, XSD_STRING};
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Relabels all blank nodes deterministically, and returns the dataset as sorted N-Quads.
+pub(super) fn canonicalize(quads: BTreeSet<Quad>) -> String {
+    let quads: Vec<Quad> = quads.into_iter().collect();
+    let mut state = State::new(&quads);
+
+    let mut hash_to_blank_nodes = BTreeMap::<String, Vec<String>>::new();
+    let blank_nodes: BTreeSet<&String> = state.blank_node_quads.keys().collect();
+    for blank_node in blank_nodes {
+        let hash = state.hash_first_degree(&quads, blank_node);
+        hash_to_blank_nodes
+            .entry(hash)
+            .or_default()
+            .push(blank_node.clone());
+    }
+
+    // blank nodes with a unique first degree hash can be labeled directly
+    let mut non_unique = vec![];
+    for (_, blank_nodes) in hash_to_blank_nodes {
+        if blank_nodes.len() == 1 {
+            state.canonical_issuer.issue(&blank_nodes[0]);
+        } else {
+            non_unique.push(blank_nodes);
+        }
+    }
+
+    for blank_nodes in non_unique {
+        let mut hash_path_list = vec![];
+        for blank_node in blank_nodes {
+            if state.canonical_issuer.has(&blank_node) {
+                continue;
+            }
+            let mut issuer = IdentifierIssuer::new("_:b");
+            issuer.issue(&blank_node);
+            hash_path_list.push(state.hash_n_degree(&quads, &blank_node, issuer));
+        }
+        hash_path_list.sort_by(|a, b| a.0.cmp(&b.0));
+        for (_, issuer) in hash_path_list {
+            for (existing, _) in issuer.issued {
+                state.canonical_issuer.issue(&existing);
+            }
+        }
+    }
+
+    quads
+        .iter()
+        .map(|quad| {
+            let relabel = |term: &Term| match term {
+                Term::BlankNode(b) => {
+                    Term::BlankNode(state.canonical_issuer.get(b).unwrap_or_else(|| b.clone()))
+                }
+                t => t.clone(),
+            };
+            Quad {
+                subject: relabel(&quad.subject),
+                predicate: quad.predicate.clone(),
+                object: relabel(&quad.object),
+                graph: quad.graph.as_ref().map(relabel),
+            }
+            .to_nquad()
+        })
+        .sorted()
+        .collect()
+}
+
+#[derive(Clone)]
+struct IdentifierIssuer {
+    prefix: &'static str,
+    /// Existing identifiers with the issued identifier, in the order they were issued
+    issued: Vec<(String, String)>,
+    lookup: HashMap<String, String>,
+}
+
+impl IdentifierIssuer {
+    fn new(prefix: &'static str) -> Self {
+        IdentifierIssuer {
+            prefix,
+            issued: vec![],
+            lookup: HashMap::new(),
+        }
+    }
+
+    fn issue(&mut self, existing: &str) -> String {
+        if let Some(issued) = self.lookup.get(existing) {
+            return issued.clone();
+        }
+        let issued = format!("{}{}", self.prefix, self.issued.len());
+        self.issued.push((existing.to_string(), issued.clone()));
+        self.lookup.insert(existing.to_string(), issued.clone());
+        issued
+    }
+
+    fn has(&self, existing: &str) -> bool {
+        self.lookup.contains_key(existing)
+    }
+
+    fn get(&self, existing: &str) -> Option<String> {
+        self.lookup.get(existing).cloned()
+    }
+}
+
+struct State {
+    /// Indices of the quads in which each blank node appears
+    blank_node_quads: HashMap<String, Vec<usize>>,
+    canonical_issuer: IdentifierIssuer,
+}
+
+impl State {
+    fn new(quads: &[Quad]) -> Self {
+        let mut blank_node_quads = HashMap::<String, Vec<usize>>::new();
+        for (i, quad) in quads.iter().enumerate() {
+            for term in quad.blank_node_components() {
+                let entry = blank_node_quads.entry(term.clone()).or_default();
+                if entry.last() != Some(&i) {
+                    entry.push(i);
+                }
+            }
+        }
+        State {
+            blank_node_quads,
+            canonical_issuer: IdentifierIssuer::new("_:c14n"),
+        }
+    }
+
+    fn hash_first_degree(&self, quads: &[Quad], reference: &str) -> String {
+        let replace = |term: &Term| match term {
+            Term::BlankNode(b) if b == reference => Term::BlankNode("_:a".to_string()),
+            Term::BlankNode(_) => Term::BlankNode("_:z".to_string()),
+            t => t.clone(),
+        };
+        let nquads: String = self.blank_node_quads[reference]
+            .iter()
+            .map(|i| {
+                let quad = &quads[*i];
+                Quad {
+                    subject: replace(&quad.subject),
+                    predicate: quad.predicate.clone(),
+                    object: replace(&quad.object),
+                    graph: quad.graph.as_ref().map(replace),
+                }
+                .to_nquad()
+            })
+            .sorted()
+            .collect();
+        sha256_hex(&nquads)
+    }
+
+    fn hash_related_blank_node(
+        &self,
+        quads: &[Quad],
+        related: &str,
+        quad: &Quad,
+        issuer: &IdentifierIssuer,
+        position: &str,
+    ) -> String {
+        let identifier = self
+            .canonical_issuer
+            .get(related)
+            .or_else(|| issuer.get(related))
+            .unwrap_or_else(|| self.hash_first_degree(quads, related));
+        let mut input = position.to_string();
+        if position != "g" {
+            input.push_str(&quad.predicate.to_nquad_term());
+        }
+        input.push_str(&identifier);
+        sha256_hex(&input)
+    }
+
+    fn hash_n_degree(
+        &self,
+        quads: &[Quad],
+        identifier: &str,
+        mut issuer: IdentifierIssuer,
+    ) -> (String, IdentifierIssuer) {
+        let mut hash_to_related = BTreeMap::<String, Vec<String>>::new();
+        for i in &self.blank_node_quads[identifier] {
+            let quad = &quads[*i];
+            let components = [
+                (Some(&quad.subject), "s"),
+                (Some(&quad.object), "o"),
+                (quad.graph.as_ref(), "g"),
+            ];
+            for (term, position) in components {
+                if let Some(Term::BlankNode(related)) = term {
+                    if related != identifier {
+                        let hash =
+                            self.hash_related_blank_node(quads, related, quad, &issuer, position);
+                        hash_to_related
+                            .entry(hash)
+                            .or_default()
+                            .push(related.clone());
+                    }
+                }
+            }
+        }
+
+        let mut data_to_hash = String::new();
+        for (related_hash, blank_nodes) in hash_to_related {
+            data_to_hash.push_str(&related_hash);
+            let mut chosen_path = String::new();
+            let mut chosen_issuer = None;
+
+            'permutations: for permutation in blank_nodes.iter().permutations(blank_nodes.len()) {
+                let mut issuer_copy = issuer.clone();
+                let mut path = String::new();
+                let mut recursion_list = vec![];
+                for related in permutation {
+                    match self.canonical_issuer.get(related) {
+                        Some(canonical) => path.push_str(&canonical),
+                        None => {
+                            if !issuer_copy.has(related) {
+                                recursion_list.push(related);
+                            }
+                            path.push_str(&issuer_copy.issue(related));
+                        }
+                    }
+                    if !chosen_path.is_empty()
+                        && path.len() >= chosen_path.len()
+                        && path > chosen_path
+                    {
+                        continue 'permutations;
+                    }
+                }
+                for related in recursion_list {
+                    let (hash, result_issuer) =
+                        self.hash_n_degree(quads, related, issuer_copy.clone());
+                    issuer_copy = result_issuer;
+                    path.push_str(&issuer_copy.issue(related));
+                    path.push('<');
+                    path.push_str(&hash);
+                    path.push('>');
+                    if !chosen_path.is_empty()
+                        && path.len() >= chosen_path.len()
+                        && path > chosen_path
+                    {
+                        continue 'permutations;
+                    }
+                }
+                if chosen_issuer.is_none() || path < chosen_path {
+                    chosen_path = path;
+                    chosen_issuer = Some(issuer_copy);
+                }
+            }
+
+            data_to_hash.push_str(&chosen_path);
+            if let Some(chosen_issuer) = chosen_issuer {
+                issuer = chosen_issuer;
+            }
+        }
+        (sha256_hex(&data_to_hash), issuer)
+    }
+}
+
+impl Quad {
+    fn blank_node_components(&self) -> impl Iterator<Item = &String> {
+        [Some(&self.subject), Some(&self.object), self.graph.as_ref()]
+            .into_iter()
+            .filter_map(|term| match term {
+                Some(Term::BlankNode(b)) => Some(b),
+                _ => None,
+            })
+    }
+
+    fn to_nquad(&self) -> String {
+        let mut nquad = format!(
+            "{} {} {} ",
+            self.subject.to_nquad_term(),
+            self.predicate.to_nquad_term(),
+            self.object.to_nquad_term()
+        );
+        if let Some(graph) = &self.graph {
+            nquad.push_str(&graph.to_nquad_term());
+            nquad.push(' ');
+        }
+        nquad.push_str(".\n");
+        nquad
+    }
+}
+
+impl Term {
+    fn to_nquad_term(&self) -> String {
+        match self {
+            Term::Iri(iri) => format!("<{iri}>"),
+            Term::BlankNode(b) => b.clone(),
+            Term::Literal {
+                value,
+                datatype,
+                language,
+            } => {
+                let mut escaped = String::from("\"");
+                for c in value.chars() {
+                    match c {
+                        '"' => escaped.push_str("\\\""),
+                        '\\' => escaped.push_str("\\\\"),
+                        '\n' => escaped.push_str("\\n"),
+                        '\r' => escaped.push_str("\\r"),
+                        c => escaped.push(c),
+                    }
+                }
+                escaped.push('"');
+                match language {
+                    Some(language) => {
+                        escaped.push('@');
+                        escaped.push_str(language);
+                    }
+                    None if datatype != XSD_STRING => {
+                        escaped.push_str("^^<");
+                        escaped.push_str(datatype);
+                        escaped.push('>');
+                    }
+                    None => {}
+                }
+                escaped
+            }
+        }
+    }
+}
+
+fn sha256_hex(input: &str) -> String {
+    Sha256::digest(input.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank(id: &str) -> Term {
+        Term::BlankNode(id.to_string())
+    }
+
+    fn iri(iri: &str) -> Term {
+        Term::Iri(iri.to_string())
+    }
+
+    #[test]
+    fn test_canonicalize_symmetric_blank_nodes() {
+        // two blank nodes which reference each other can only be distinguished with n-degree
+        // hashing, and the result must not depend on their original labels
+        let dataset = |a: &str, b: &str| {
+            BTreeSet::from([
+                Quad {
+                    subject: blank(a),
+                    predicate: iri("http://example.com/p"),
+                    object: blank(b),
+                    graph: None,
+                },
+                Quad {
+                    subject: blank(b),
+                    predicate: iri("http://example.com/p"),
+                    object: blank(a),
+                    graph: None,
+                },
+                Quad {
+                    subject: blank(a),
+                    predicate: iri("http://example.com/q"),
+                    object: iri("http://example.com/x"),
+                    graph: None,
+                },
+            ])
+        };
+        let canonical = canonicalize(dataset("_:b0", "_:b1"));
+        assert_eq!(canonical, canonicalize(dataset("_:b1", "_:b0")));
+        assert_eq!(canonical.lines().count(), 3);
+        assert!(canonical.contains("_:c14n0"));
+        assert!(canonical.contains("_:c14n1"));
+    }
+
+    #[test]
+    fn test_canonicalize_identical_blank_nodes() {
+        let dataset = BTreeSet::from([
+            Quad {
+                subject: iri("http://example.com/s"),
+                predicate: iri("http://example.com/p"),
+                object: blank("_:b0"),
+                graph: None,
+            },
+            Quad {
+                subject: iri("http://example.com/s"),
+                predicate: iri("http://example.com/p"),
+                object: blank("_:b1"),
+                graph: None,
+            },
+        ]);
+        let expected = "<http://example.com/s> <http://example.com/p> _:c14n0 .\n\
+            <http://example.com/s> <http://example.com/p> _:c14n1 .\n";
+        assert_eq!(canonicalize(dataset), expected);
+    }
+
+    /// Parses N-Quads which only contain IRIs and blank nodes, in the default graph
+    fn parse_nquads(nquads: &str) -> BTreeSet<Quad> {
+        let term = |t: &str| match t.strip_prefix('<') {
+            Some(iri) => Term::Iri(iri.trim_end_matches('>').to_string()),
+            None => blank(t),
+        };
+        nquads
+            .lines()
+            .map(|line| {
+                let terms: Vec<_> = line.split_whitespace().collect();
+                Quad {
+                    subject: term(terms[0]),
+                    predicate: term(terms[1]),
+                    object: term(terms[2]),
+                    graph: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Blank nodes with unique first degree hashes, example from the rdf-canon specification
+    #[test]
+    fn test_rdf_canon_unique_hashes() {
+        let input = "<http://example.com/#p> <http://example.com/#q> _:e0 .\n\
+            <http://example.com/#p> <http://example.com/#r> _:e1 .\n\
+            _:e0 <http://example.com/#s> <http://example.com/#u> .\n\
+            _:e1 <http://example.com/#t> <http://example.com/#u> .";
+        let quads: Vec<_> = parse_nquads(input).into_iter().collect();
+        let state = State::new(&quads);
+        assert_eq!(
+            state.hash_first_degree(&quads, "_:e0"),
+            "21d1dd5ba21f3dee9d76c0c00c260fa6f5d5d65315099e553026f4828d0dc77a"
+        );
+        assert_eq!(
+            state.hash_first_degree(&quads, "_:e1"),
+            "6fa0b9bdb376852b5743ff39ca4cbf7ea14d34966b2828478fbf222e7c764473"
+        );
+
+        let expected = "<http://example.com/#p> <http://example.com/#q> _:c14n0 .\n\
+            <http://example.com/#p> <http://example.com/#r> _:c14n1 .\n\
+            _:c14n0 <http://example.com/#s> <http://example.com/#u> .\n\
+            _:c14n1 <http://example.com/#t> <http://example.com/#u> .\n";
+        assert_eq!(canonicalize(parse_nquads(input)), expected);
+    }
+
+    /// Blank nodes with shared first degree hashes which need n-degree hashing, example from the
+    /// rdf-canon specification
+    #[test]
+    fn test_rdf_canon_shared_hashes() {
+        let input = "<http://example.com/#p> <http://example.com/#q> _:e0 .\n\
+            <http://example.com/#p> <http://example.com/#q> _:e1 .\n\
+            _:e0 <http://example.com/#p> _:e2 .\n\
+            _:e1 <http://example.com/#p> _:e3 .\n\
+            _:e2 <http://example.com/#r> _:e3 .";
+        let quads: Vec<_> = parse_nquads(input).into_iter().collect();
+        let state = State::new(&quads);
+        let first_degree = [
+            (
+                "_:e0",
+                "3b26142829b8887d011d779079a243bd61ab53c3990d550320a17b59ade6ba36",
+            ),
+            (
+                "_:e1",
+                "3b26142829b8887d011d779079a243bd61ab53c3990d550320a17b59ade6ba36",
+            ),
+            (
+                "_:e2",
+                "15973d39de079913dac841ac4fa8c4781c0febfba5e83e5c6e250869587f8659",
+            ),
+            (
+                "_:e3",
+                "7e790a99273eed1dc57e43205d37ce232252c85b26ca4a6ff74ff3b5aea7bccd",
+            ),
+        ];
+        for (blank_node, hash) in first_degree {
+            assert_eq!(state.hash_first_degree(&quads, blank_node), hash);
+        }
+
+        let expected = "<http://example.com/#p> <http://example.com/#q> _:c14n2 .\n\
+            <http://example.com/#p> <http://example.com/#q> _:c14n3 .\n\
+            _:c14n0 <http://example.com/#r> _:c14n1 .\n\
+            _:c14n2 <http://example.com/#p> _:c14n1 .\n\
+            _:c14n3 <http://example.com/#p> _:c14n0 .\n";
+        assert_eq!(canonicalize(parse_nquads(input)), expected);
+    }
+}
@@ -0,0 +1,228 @@
+//! Verify Linked Data Signatures which are embedded in activities
+//!
+//! Mastodon signs public activities with an `RsaSignature2017` object in the `signature` field.
+//! This allows relays and other servers to forward the activity, while the receiver can still
+//! check that it was created by the actor. The JSON-LD documents are canonicalized using only
+//! the context documents which are bundled with this library, so verification never makes any
+//! network requests.
+//!
+//! <https://docs.joinmastodon.org/spec/security/#ld>
+
+use crate::{
+    config::Data,
+    error::Error,
+    http_signatures::public_key_cached,
+    protocol::{json_ld::canonicalize, public_key::main_key_id},
+    traits::{Actor, Object},
+};
+use base64::{engine::general_purpose::STANDARD as Base64, Engine};
+use openssl::{hash::MessageDigest, sign::Verifier};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use url::Url;
+
+const SIGNATURE_TYPE: &str = "RsaSignature2017";
+const SIGNATURE_CONTEXT: &str = "https://w3id.org/identity/v1";
+
+/// Verifies the `RsaSignature2017` Linked Data Signature of a JSON-LD document.
+///
+/// The parsed public key of the actor is cached in the same way as for HTTP signatures. Returns
+/// [Error::ActivitySignatureInvalid] if the document has no signature, if the signature was
+/// created by another actor, or if it doesn't match the document.
+pub async fn verify_ld_signature<A: Actor>(
+    json: &Value,
+    actor: &A,
+    data: &Data<<A as Object>::DataType>,
+) -> Result<(), Error> {
+    let signature = json
+        .get("signature")
+        .and_then(Value::as_object)
+        .ok_or(Error::ActivitySignatureInvalid)?;
+    if signature.get("type").and_then(Value::as_str) != Some(SIGNATURE_TYPE) {
+        return Err(Error::ActivitySignatureInvalid);
+    }
+    let creator = signature
+        .get("creator")
+        .and_then(Value::as_str)
+        .and_then(|c| Url::parse(c).ok())
+        .ok_or(Error::ActivitySignatureInvalid)?;
+    let key_id = main_key_id(&actor.id());
+    if creator.as_str() != key_id {
+        debug!("LD signature was created by {creator}, but expected key {key_id}");
+        return Err(Error::ActivitySignatureInvalid);
+    }
+    let signature_value = signature
+        .get("signatureValue")
+        .and_then(Value::as_str)
+        .and_then(|s| Base64.decode(s).ok())
+        .ok_or(Error::ActivitySignatureInvalid)?;
+
+    let to_be_verified = signing_input(json)?;
+    let public_key = public_key_cached(&data.config, actor).await?;
+    let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key).map_err(Error::other)?;
+    verifier
+        .update(to_be_verified.as_bytes())
+        .map_err(Error::other)?;
+    if verifier.verify(&signature_value).map_err(Error::other)? {
+        debug!("verified LD signature from {key_id}");
+        Ok(())
+    } else {
+        Err(Error::ActivitySignatureInvalid)
+    }
+}
+
+/// Returns the string which is signed, consisting of the hashes of the canonicalized signature
+/// options and of the canonicalized document.
+fn signing_input(json: &Value) -> Result<String, Error> {
+    let (Some(document), Some(signature)) = (
+        json.as_object(),
+        json.get("signature").and_then(Value::as_object),
+    ) else {
+        return Err(Error::ActivitySignatureInvalid);
+    };
+    let mut options = signature.clone();
+    options.remove("type");
+    options.remove("id");
+    options.remove("signatureValue");
+    options.insert("@context".to_string(), SIGNATURE_CONTEXT.into());
+    let mut document = document.clone();
+    document.remove("signature");
+
+    let hash = |value: Value| -> Result<String, Error> {
+        let canonical = canonicalize(&value).map_err(|e| {
+            debug!("Failed to canonicalize JSON-LD document: {e}");
+            Error::ActivitySignatureInvalid
+        })?;
+        Ok(hex(&Sha256::digest(canonical.as_bytes())))
+    };
+    Ok(hash(options.into())? + &hash(document.into())?)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Adds an `RsaSignature2017` to the document, only used for tests
+#[cfg(test)]
+pub(crate) fn sign_ld(json: &mut Value, actor_id: &Url, private_key_pem: &str) {
+    sign_ld_with_creator(json, &main_key_id(actor_id), private_key_pem)
+}
+
+#[cfg(test)]
+fn sign_ld_with_creator(json: &mut Value, creator: &str, private_key_pem: &str) {
+    use openssl::{pkey::PKey, sign::Signer};
+    json["signature"] = serde_json::json!({
+        "type": SIGNATURE_TYPE,
+        "creator": creator,
+        "created": "2023-01-01T00:00:00Z",
+    });
+    let to_be_signed = signing_input(json).unwrap();
+    let private_key = PKey::private_key_from_pem(private_key_pem.as_bytes()).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &private_key).unwrap();
+    signer.update(to_be_signed.as_bytes()).unwrap();
+    json["signature"]["signatureValue"] = Base64.encode(signer.sign_to_vec().unwrap()).into();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        traits::tests::{DbConnection, DB_USER, DB_USER_KEYPAIR},
+    };
+    use serde_json::json;
+
+    async fn data() -> Data<DbConnection> {
+        FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection)
+            .build()
+            .await
+            .unwrap()
+            .to_request_data()
+    }
+
+    fn signed_note() -> Value {
+        let mut note = json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": "https://localhost/123/create/1",
+            "type": "Create",
+            "actor": "https://localhost/123",
+            "to": ["https://www.w3.org/ns/activitystreams#Public"],
+            "object": {
+                "id": "https://localhost/123/note/1",
+                "type": "Note",
+                "attributedTo": "https://localhost/123",
+                "content": "Hello world",
+            }
+        });
+        sign_ld(
+            &mut note,
+            &DB_USER.federation_id,
+            &DB_USER_KEYPAIR.private_key,
+        );
+        note
+    }
+
+    #[tokio::test]
+    async fn test_verify_ld_signature() {
+        let data = data().await;
+        let note = signed_note();
+        assert!(verify_ld_signature(&note, &*DB_USER, &data).await.is_ok());
+
+        // the parsed key is cached
+        let key_id = main_key_id(&DB_USER.federation_id);
+        assert!(data.config.public_key_cache.get(&key_id).is_some());
+        assert!(verify_ld_signature(&note, &*DB_USER, &data).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_verify_ld_signature_tampered() {
+        let data = data().await;
+        let mut note = signed_note();
+        note["object"]["content"] = "Goodbye world".into();
+        assert_eq!(
+            verify_ld_signature(&note, &*DB_USER, &data).await,
+            Err(Error::ActivitySignatureInvalid)
+        );
+
+        let mut note = signed_note();
+        note["signature"]["created"] = "2024-01-01T00:00:00Z".into();
+        assert_eq!(
+            verify_ld_signature(&note, &*DB_USER, &data).await,
+            Err(Error::ActivitySignatureInvalid)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_ld_signature_wrong_actor() {
+        let data = data().await;
+        let mut note = signed_note();
+        note["signature"]["creator"] = "https://example.com/456#main-key".into();
+        assert_eq!(
+            verify_ld_signature(&note, &*DB_USER, &data).await,
+            Err(Error::ActivitySignatureInvalid)
+        );
+
+        // the creator must be the main key of the actor, not any other fragment of its id
+        for creator in [
+            format!("{}#other-key", DB_USER.federation_id),
+            DB_USER.federation_id.to_string(),
+        ] {
+            let mut note = signed_note();
+            sign_ld_with_creator(&mut note, &creator, &DB_USER_KEYPAIR.private_key);
+            assert_eq!(
+                verify_ld_signature(&note, &*DB_USER, &data).await,
+                Err(Error::ActivitySignatureInvalid)
+            );
+        }
+
+        let mut note = signed_note();
+        note.as_object_mut().unwrap().remove("signature");
+        assert_eq!(
+            verify_ld_signature(&note, &*DB_USER, &data).await,
+            Err(Error::ActivitySignatureInvalid)
+        );
+    }
+}
@@ -2,6 +2,9 @@
 
 pub mod context;
 pub mod helpers;
+pub mod integrity_proof;
+pub(crate) mod json_ld;
+pub mod ld_signature;
 pub mod public_key;
 pub mod values;
 pub mod verification;
@@ -11,14 +11,13 @@ use std::{
     pin::Pin,
     task::{Context, Poll},
 };
-
-/// 200KB
-const MAX_BODY_SIZE: usize = 204800;
+use url::Url;
 
 pin_project! {
     pub struct BytesFuture {
         #[pin]
         stream: BoxStream<'static, reqwest::Result<Bytes>>,
+        url: Url,
         limit: usize,
         aggregator: BytesMut,
     }
@@ -36,7 +35,10 @@ impl Future for BytesFuture {
             {
                 this.aggregator.put(chunk);
                 if this.aggregator.len() > *this.limit {
-                    return Poll::Ready(Err(Error::ResponseBodyLimit));
+                    return Poll::Ready(Err(Error::ResponseBodyLimit(
+                        this.url.clone(),
+                        *this.limit,
+                    )));
                 }
 
                 continue;
@@ -92,7 +94,8 @@ impl Future for TextFuture {
 /// Reqwest doesn't limit the response body size by default nor does it offer an option to configure one.
 /// Since we have to fetch data from untrusted sources, not restricting the maximum size is a DoS hazard for us.
 ///
-/// This shim reimplements the `bytes`, `json`, and `text` functions and restricts the bodies to 100KB.
+/// This shim reimplements the `bytes`, `json`, and `text` functions and restricts the bodies to
+/// the given number of bytes, see [ResponseSizeLimits](crate::fetch::ResponseSizeLimits).
 ///
 /// TODO: Remove this shim as soon as reqwest gets support for size-limited bodies.
 pub trait ResponseExt {
@@ -101,11 +104,11 @@ pub trait ResponseExt {
     type TextFuture;
 
     /// Size limited version of `bytes` to work around a reqwest issue. Check [`ResponseExt`] docs for details.
-    fn bytes_limited(self) -> Self::BytesFuture;
+    fn bytes_limited(self, limit: usize) -> Self::BytesFuture;
     /// Size limited version of `json` to work around a reqwest issue. Check [`ResponseExt`] docs for details.
-    fn json_limited<T>(self) -> Self::JsonFuture<T>;
+    fn json_limited<T>(self, limit: usize) -> Self::JsonFuture<T>;
     /// Size limited version of `text` to work around a reqwest issue. Check [`ResponseExt`] docs for details.
-    fn text_limited(self) -> Self::TextFuture;
+    fn text_limited(self, limit: usize) -> Self::TextFuture;
 }
 
 impl ResponseExt for Response {
@@ -113,24 +116,25 @@ impl ResponseExt for Response {
     type JsonFuture<T> = JsonFuture<T>;
     type TextFuture = TextFuture;
 
-    fn bytes_limited(self) -> Self::BytesFuture {
+    fn bytes_limited(self, limit: usize) -> Self::BytesFuture {
         BytesFuture {
+            url: self.url().clone(),
             stream: Box::pin(self.bytes_stream()),
-            limit: MAX_BODY_SIZE,
+            limit,
             aggregator: BytesMut::new(),
         }
     }
 
-    fn json_limited<T>(self) -> Self::JsonFuture<T> {
+    fn json_limited<T>(self, limit: usize) -> Self::JsonFuture<T> {
         JsonFuture {
             _t: PhantomData,
-            future: self.bytes_limited(),
+            future: self.bytes_limited(limit),
         }
     }
 
-    fn text_limited(self) -> Self::TextFuture {
+    fn text_limited(self, limit: usize) -> Self::TextFuture {
         TextFuture {
-            future: self.bytes_limited(),
+            future: self.bytes_limited(limit),
         }
     }
 }
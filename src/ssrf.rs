@@ -0,0 +1,296 @@
+//! Protection against server-side request forgery
+//!
+//! Remote servers control which urls are fetched by this library, for example through object ids
+//! or redirects. Without protection they could make requests to services in the local network,
+//! such as `http://10.0.0.5/` or a domain which resolves to `127.0.0.1`.
+//!
+//! [SsrfGuard] checks the resolved IP addresses of all outgoing requests, and only allows
+//! connections to public addresses. It is used by the default HTTP client of
+//! [FederationConfig](crate::config::FederationConfig), including in debug mode. If you
+//! supply your own client with
+//! [FederationConfigBuilder::client](crate::config::FederationConfigBuilder::client), create it
+//! with [SsrfGuard::client_builder] to keep the protection. Tests which run local servers need
+//! to permit loopback addresses with
+//! [FederationConfigBuilder::ssrf_allowlist](crate::config::FederationConfigBuilder::ssrf_allowlist).
+//!
+//! This module requires the `ssrf` feature, which is enabled by default.
+//!
+//! ```
+//! # use activitypub_federation::ssrf::SsrfGuard;
+//! // allow connections to a service in the local network
+//! let guard = SsrfGuard::new(vec!["10.0.0.5/32".parse()?]);
+//! let client = guard.client_builder().build()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use hyper::client::connect::dns::Name;
+use ipnet::IpNet;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    redirect::Policy,
+    ClientBuilder,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use tracing::debug;
+
+/// Maximum number of redirects which are followed, same as the reqwest default
+const MAX_REDIRECTS: usize = 10;
+
+/// DNS resolver and redirect policy which reject private, loopback, link-local, CGNAT and
+/// multicast addresses.
+///
+/// Addresses in the allowlist are always permitted, this is mainly useful for tests.
+#[derive(Clone, Debug, Default)]
+pub struct SsrfGuard {
+    allowlist: Arc<Vec<IpNet>>,
+}
+
+impl SsrfGuard {
+    /// Creates a new guard which additionally permits the given networks.
+    pub fn new(allowlist: Vec<IpNet>) -> Self {
+        SsrfGuard {
+            allowlist: Arc::new(allowlist),
+        }
+    }
+
+    /// Returns true if connections to this address are permitted.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        is_global(ip) || self.allowlist.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Returns a client builder which uses this guard for DNS resolution and redirects.
+    pub fn client_builder(&self) -> ClientBuilder {
+        let guard = self.clone();
+        let redirect_policy = Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            // ip addresses in urls don't go through the resolver, so they need to be checked here
+            let ip = match attempt.url().host() {
+                Some(url::Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+                Some(url::Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+                _ => None,
+            };
+            match ip {
+                Some(ip) if !guard.is_allowed(ip) => {
+                    let error = format!("Redirect to non-public address {ip} is not allowed");
+                    attempt.error(error)
+                }
+                _ => attempt.follow(),
+            }
+        });
+        ClientBuilder::new()
+            .dns_resolver(Arc::new(self.clone()))
+            .redirect(redirect_policy)
+    }
+}
+
+impl Resolve for SsrfGuard {
+    fn resolve(&self, name: Name) -> Resolving {
+        let guard = self.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| {
+                    let allowed = guard.is_allowed(addr.ip());
+                    if !allowed {
+                        debug!("Rejecting non-public address {} of {host}", addr.ip());
+                    }
+                    allowed
+                })
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{host} does not resolve to any public address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Returns false for addresses which are not reachable on the public internet.
+pub(crate) fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_global_v4(ip),
+        IpAddr::V6(ip) => is_global_v6(ip),
+    }
+}
+
+fn is_global_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_multicast()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        // "this network", 0.0.0.0/8
+        || a == 0
+        // shared address space for CGNAT, 100.64.0.0/10
+        || (a == 100 && (b & 0b1100_0000) == 64)
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking, 198.18.0.0/15
+        || (a == 198 && (b & 0xfe) == 18)
+        // reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_global_v6(ip: Ipv6Addr) -> bool {
+    // addresses with an embedded ipv4 address, ::ffff:0:0/96 and 64:ff9b::/96
+    if let Some(ipv4) = ip.to_ipv4_mapped() {
+        return is_global_v4(ipv4);
+    }
+    let segments = ip.segments();
+    let octets = ip.octets();
+    // deprecated ipv4 compatible, ::/96, which also contains :: and ::1
+    if segments[..6] == [0, 0, 0, 0, 0, 0] || segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., a, b, c, d] = octets;
+        return is_global_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    // 6to4, 2002::/16, routes to the ipv4 address in the following 32 bits
+    if segments[0] == 0x2002 {
+        let [_, _, a, b, c, d, ..] = octets;
+        return is_global_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local, fc00::/7
+        || (segments[0] & 0xfe00) == 0xfc00
+        // link local, fe80::/10
+        || (segments[0] & 0xffc0) == 0xfe80
+        // deprecated site local, fec0::/10
+        || (segments[0] & 0xffc0) == 0xfec0
+        // documentation, 2001:db8::/32
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8)
+        // teredo, 2001::/32, can tunnel to arbitrary ipv4 addresses
+        || (segments[0] == 0x2001 && segments[1] == 0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_global() {
+        let global = [
+            "1.1.1.1",
+            "93.184.216.34",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+            "198.20.0.1",
+            "2002:101:101::1",
+        ];
+        for ip in global {
+            assert!(is_global(ip.parse().unwrap()), "{ip}");
+        }
+        let non_global = [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.0.0.5",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "100.127.255.255",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a00:5",
+            "192.0.0.8",
+            "198.18.0.1",
+            "198.19.255.255",
+            "::127.0.0.1",
+            "::10.0.0.5",
+            "2002:7f00:1::1",
+            "2002:c0a8:101::1",
+            "2001:0:4136:e378:8000:63bf:3fff:fdd2",
+        ];
+        for ip in non_global {
+            assert!(!is_global(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        assert!(!SsrfGuard::default().is_allowed(localhost));
+        let guard = SsrfGuard::new(vec!["127.0.0.0/8".parse().unwrap()]);
+        assert!(guard.is_allowed(localhost));
+        assert!(!guard.is_allowed("10.0.0.5".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_localhost() {
+        let name: Name = "localhost".parse().unwrap();
+        assert!(SsrfGuard::default().resolve(name.clone()).await.is_err());
+
+        let guard = SsrfGuard::new(vec![
+            "127.0.0.0/8".parse().unwrap(),
+            "::1/128".parse().unwrap(),
+        ]);
+        let addrs: Vec<_> = guard.resolve(name).await.unwrap().collect();
+        assert!(addrs.iter().all(|a| a.ip().is_loopback()));
+    }
+
+    #[tokio::test]
+    async fn test_redirect_to_non_public_address() {
+        use crate::fetch::tests::spawn_test_server;
+        use axum::{response::Redirect, routing::get, Router};
+
+        let base = spawn_test_server(|base| {
+            let port = base.port().unwrap();
+            Router::new()
+                .route(
+                    "/private",
+                    get(move || async move {
+                        Redirect::temporary(&format!("http://127.0.0.2:{port}/object"))
+                    }),
+                )
+                .route(
+                    "/allowed",
+                    get(move || async move {
+                        Redirect::temporary(&format!("http://127.0.0.1:{port}/object"))
+                    }),
+                )
+                .route("/object", get(|| async { "object" }))
+        });
+        // only the test server itself is reachable, without debug mode involved
+        let client = SsrfGuard::new(vec!["127.0.0.1/32".parse().unwrap()])
+            .client_builder()
+            .build()
+            .unwrap();
+
+        let error = client
+            .get(base.join("private").unwrap())
+            .send()
+            .await
+            .unwrap_err();
+        assert!(error.is_redirect(), "{error:?}");
+        assert!(
+            format!("{error:#}").contains("non-public address 127.0.0.2"),
+            "{error:#}"
+        );
+
+        let res = client
+            .get(base.join("allowed").unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            res.url().as_str(),
+            format!("http://127.0.0.1:{}/object", base.port().unwrap())
+        );
+        assert_eq!(res.text().await.unwrap(), "object");
+    }
+}
@@ -1,10 +1,15 @@
 //! Traits which need to be implemented for federated data types
 
-use crate::{config::Data, protocol::public_key::PublicKey};
+use crate::{
+    config::Data,
+    fetch::CacheValidators,
+    http_signatures::Signer,
+    protocol::public_key::PublicKey,
+};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use std::{fmt::Debug, ops::Deref};
+use std::{fmt::Debug, ops::Deref, sync::Arc, time::Duration};
 use url::Url;
 
 /// Helper for converting between database structs and federated protocol structs.
@@ -110,12 +115,50 @@ pub trait Object: Sized + Debug {
     /// update mechanism prescribed. It is possible to send `Update/Person` activities for profile
     /// changes, but not all implementations do this, so `last_refreshed_at` is still necessary.
     ///
-    /// The object is refetched if `last_refreshed_at` value is more than 24 hours ago. In debug
-    /// mode this is reduced to 20 seconds.
+    /// The object is refetched if `last_refreshed_at` is longer ago than
+    /// [Object::refetch_interval].
     fn last_refreshed_at(&self) -> Option<DateTime<Utc>> {
         None
     }
 
+    /// How long this object is considered fresh after [Object::last_refreshed_at].
+    ///
+    /// If this returns `None`, the value from
+    /// [FederationConfigBuilder::refetch_interval](crate::config::FederationConfigBuilder::refetch_interval)
+    /// is used, which is 24 hours by default. Actors may want a shorter interval so that key
+    /// changes are noticed quickly, while posts which rarely change can use a longer one.
+    fn refetch_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the `ETag` and `Last-Modified` values from the last fetch of this object.
+    ///
+    /// If this returns `Some`, a stale object is refetched with `If-None-Match` and
+    /// `If-Modified-Since` headers. If the remote server responds with `304 Not Modified`,
+    /// [Object::mark_refreshed] is called instead of [Object::verify] and [Object::from_json].
+    /// The values are passed to [Object::set_cache_validators] after each fetch.
+    fn cache_validators(&self) -> Option<CacheValidators> {
+        None
+    }
+
+    /// Store the `ETag` and `Last-Modified` values of a fetched object, so that they can be
+    /// returned from [Object::cache_validators]. Called after [Object::from_json] if the response
+    /// contained at least one of the headers.
+    async fn set_cache_validators(
+        self,
+        _validators: CacheValidators,
+        _data: &Data<Self::DataType>,
+    ) -> Result<Self, Self::Error> {
+        Ok(self)
+    }
+
+    /// Called when a refetch of the object returned `304 Not Modified`. Should update the value
+    /// of [Object::last_refreshed_at] in the database, so that the object isn't refetched again
+    /// immediately.
+    async fn mark_refreshed(self, _data: &Data<Self::DataType>) -> Result<Self, Self::Error> {
+        Ok(self)
+    }
+
     /// Try to read the object with given `id` from local database.
     ///
     /// Should return `Ok(None)` if not found.
@@ -124,6 +167,26 @@ pub trait Object: Sized + Debug {
         data: &Data<Self::DataType>,
     ) -> Result<Option<Self>, Self::Error>;
 
+    /// Try to read multiple objects from local database, for example with a single
+    /// `SELECT ... WHERE id IN (...)` query. Used by
+    /// [ObjectId::dereference_many](crate::fetch::object_id::ObjectId::dereference_many).
+    ///
+    /// Must return one entry for each id in the same order, which is `None` if the object was
+    /// not found. The default implementation calls [Object::read_from_id] for each id.
+    async fn read_from_ids(
+        object_ids: Vec<Url>,
+        data: &Data<Self::DataType>,
+    ) -> Result<Vec<Option<Self>>, Self::Error>
+    where
+        Self: Send,
+    {
+        let mut objects = Vec::with_capacity(object_ids.len());
+        for object_id in object_ids {
+            objects.push(Self::read_from_id(object_id, data).await?);
+        }
+        Ok(objects)
+    }
+
     /// Mark remote object as deleted in local database.
     ///
     /// Called when a `Delete` activity is received, or if fetch returns a `Tombstone` object.
@@ -246,6 +309,31 @@ pub trait Actor: Object + Send + 'static {
     /// actor keypair.
     fn private_key_pem(&self) -> Option<String>;
 
+    /// Custom signer for outgoing requests of this actor. If this returns `Some`, it is used
+    /// instead of [Actor::private_key_pem], so that the private key doesn't need to be available
+    /// in the application. See [Signer] for details.
+    fn signer(&self) -> Option<Arc<dyn Signer>> {
+        None
+    }
+
+    /// The actor's Ed25519 public key for verifying
+    /// [integrity proofs](crate::protocol::integrity_proof) of incoming objects.
+    ///
+    /// Remote actors publish this key as [Multikey](crate::protocol::integrity_proof::Multikey) in
+    /// their `assertionMethod` field.
+    fn ed25519_public_key_pem(&self) -> Option<&str> {
+        None
+    }
+
+    /// The actor's Ed25519 private key. If this returns `Some`, outgoing activities are signed
+    /// with an [integrity proof](crate::protocol::integrity_proof).
+    ///
+    /// Use [generate_ed25519_keypair](crate::protocol::integrity_proof::generate_ed25519_keypair)
+    /// to create the keypair.
+    fn ed25519_private_key_pem(&self) -> Option<String> {
+        None
+    }
+
     /// The inbox where activities for this user should be sent to
     fn inbox(&self) -> Url;
 
@@ -342,7 +430,11 @@ pub mod tests {
     use crate::{
         fetch::object_id::ObjectId,
         http_signatures::{generate_actor_keypair, Keypair},
-        protocol::{public_key::PublicKey, verification::verify_domains_match},
+        protocol::{
+            integrity_proof::{generate_ed25519_keypair, Ed25519Keypair},
+            public_key::PublicKey,
+            verification::verify_domains_match,
+        },
     };
     use activitystreams_kinds::{activity::FollowType, actor::PersonType};
     use anyhow::Error;
@@ -385,18 +477,40 @@ pub mod tests {
         pub public_key: String,
         #[allow(dead_code)]
         private_key: Option<String>,
+        pub ed25519_public_key: Option<String>,
+        ed25519_private_key: Option<String>,
         pub followers: Vec<Url>,
         pub local: bool,
     }
 
     pub static DB_USER_KEYPAIR: Lazy<Keypair> = Lazy::new(|| generate_actor_keypair().unwrap());
 
+    pub static DB_USER_ED25519_KEYPAIR: Lazy<Ed25519Keypair> =
+        Lazy::new(|| generate_ed25519_keypair().unwrap());
+
     pub static DB_USER: Lazy<DbUser> = Lazy::new(|| DbUser {
         name: String::new(),
         federation_id: "https://localhost/123".parse().unwrap(),
         inbox: "https://localhost/123/inbox".parse().unwrap(),
         public_key: DB_USER_KEYPAIR.public_key.clone(),
         private_key: Some(DB_USER_KEYPAIR.private_key.clone()),
+        ed25519_public_key: Some(DB_USER_ED25519_KEYPAIR.public_key.clone()),
+        ed25519_private_key: Some(DB_USER_ED25519_KEYPAIR.private_key.clone()),
+        followers: vec![],
+        local: false,
+    });
+
+    pub static DB_RELAY_KEYPAIR: Lazy<Keypair> = Lazy::new(|| generate_actor_keypair().unwrap());
+
+    /// Another actor with its own keypair, which delivers activities of [DB_USER]
+    pub static DB_RELAY: Lazy<DbUser> = Lazy::new(|| DbUser {
+        name: "relay".to_string(),
+        federation_id: "https://relay.localhost/actor".parse().unwrap(),
+        inbox: "https://relay.localhost/inbox".parse().unwrap(),
+        public_key: DB_RELAY_KEYPAIR.public_key.clone(),
+        private_key: Some(DB_RELAY_KEYPAIR.private_key.clone()),
+        ed25519_public_key: None,
+        ed25519_private_key: None,
         followers: vec![],
         local: false,
     });
@@ -408,9 +522,12 @@ pub mod tests {
         type Error = Error;
 
         async fn read_from_id(
-            _object_id: Url,
+            object_id: Url,
             _data: &Data<Self::DataType>,
         ) -> Result<Option<Self>, Self::Error> {
+            if object_id == DB_RELAY.federation_id {
+                return Ok(Some(DB_RELAY.clone()));
+            }
             Ok(Some(DB_USER.clone()))
         }
 
@@ -443,6 +560,8 @@ pub mod tests {
                 inbox: json.inbox,
                 public_key: json.public_key.public_key_pem,
                 private_key: None,
+                ed25519_public_key: None,
+                ed25519_private_key: None,
                 followers: vec![],
                 local: false,
             })
@@ -462,6 +581,14 @@ pub mod tests {
             self.private_key.clone()
         }
 
+        fn ed25519_public_key_pem(&self) -> Option<&str> {
+            self.ed25519_public_key.as_deref()
+        }
+
+        fn ed25519_private_key_pem(&self) -> Option<String> {
+            self.ed25519_private_key.clone()
+        }
+
         fn inbox(&self) -> Url {
             self.inbox.clone()
         }
@@ -499,6 +626,47 @@ pub mod tests {
         }
     }
 
+    /// [Follow] which fails to receive unless the raw activity with its signature headers is
+    /// available for forwarding
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct ForwardableFollow(pub Follow);
+
+    #[async_trait]
+    impl ActivityHandler for ForwardableFollow {
+        type DataType = DbConnection;
+        type Error = Error;
+
+        fn id(&self) -> &Url {
+            &self.0.id
+        }
+
+        fn actor(&self) -> &Url {
+            self.0.actor.inner()
+        }
+
+        async fn verify(&self, _: &Data<Self::DataType>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn receive(self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+            let received = data
+                .received_activity()
+                .ok_or_else(|| anyhow::anyhow!("missing received activity"))?;
+            let json: serde_json::Value = serde_json::from_slice(&received.body)?;
+            if received.id() != &self.0.id || json["id"] != self.0.id.as_str() {
+                return Err(anyhow::anyhow!("received activity doesn't match"));
+            }
+            let headers = received.headers();
+            let signed =
+                headers.contains_key("signature") || headers.contains_key("signature-input");
+            if !signed || !headers.contains_key("digest") && !headers.contains_key("content-digest")
+            {
+                return Err(anyhow::anyhow!("missing signature headers: {headers:?}"));
+            }
+            Ok(())
+        }
+    }
+
     #[derive(Clone, Debug, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Note {}